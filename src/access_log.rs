@@ -0,0 +1,104 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use crate::date::{UtcDateTime, MONTHS};
+
+/// Where the access log goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogSink {
+    Stdout,
+    // appended to, the file is created if it doesn't exist yet.
+    File(PathBuf),
+}
+
+/// Writes one line per served request, shared by every worker of the pool.
+pub struct AccessLog {
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+impl AccessLog {
+    /// Open the sink, see *LogSink*.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the log file can't be opened for appending.
+    pub fn open(sink: &LogSink) -> io::Result<AccessLog> {
+        let writer: Box<dyn Write + Send> = match sink {
+            LogSink::Stdout => Box::new(io::stdout()),
+            LogSink::File(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        };
+        Ok(AccessLog { sink: Mutex::new(writer) })
+    }
+    /// Append a line, a newline is added.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the sink can't be written to.
+    pub fn write(&self, line: &str) -> io::Result<()> {
+        let mut sink = self.sink.lock().unwrap();
+        // a single write keeps lines from different workers from interleaving.
+        sink.write_all(format!("{}\n", line).as_bytes())?;
+        sink.flush()
+    }
+}
+
+/// Format a request in the Common Log Format, .e.g.
+/// `127.0.0.1 - - [14/Oct/2026:08:05:09 +0000] "GET / HTTP/1.1" 200 170`.
+///
+/// An unknown client address is written as `-`, and so is an empty body.
+pub fn common_log_format(
+    client: Option<IpAddr>,
+    time: &UtcDateTime,
+    request_line: &str,
+    status: u16,
+    bytes: usize,
+) -> String {
+    let client = client.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+    let bytes = if bytes == 0 { "-".to_string() } else { bytes.to_string() };
+    format!(
+        "{} - - [{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] \"{}\" {} {}",
+        client,
+        time.day,
+        MONTHS[time.month as usize - 1],
+        time.year,
+        time.hour,
+        time.minute,
+        time.second,
+        // a quote inside the request line would make the line ambiguous to parse.
+        request_line.replace('"', "\\\""),
+        status,
+        bytes
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn a_line_has_the_common_log_format_shape() {
+        // 2026-10-14 08:05:09 UTC.
+        let time = UtcDateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(1_791_965_109));
+        let client = Some("127.0.0.1".parse().unwrap());
+        let line = common_log_format(client, &time, "GET / HTTP/1.1", 200, 170);
+        assert_eq!(line, "127.0.0.1 - - [14/Oct/2026:08:05:09 +0000] \"GET / HTTP/1.1\" 200 170");
+        let line = common_log_format(None, &time, "GET /\"x HTTP/1.1", 404, 0);
+        assert_eq!(line, "- - - [14/Oct/2026:08:05:09 +0000] \"GET /\\\"x HTTP/1.1\" 404 -");
+        let client = Some("::1".parse().unwrap());
+        assert!(common_log_format(client, &time, "-", 400, 12).starts_with("::1 - - ["));
+    }
+
+    #[test]
+    fn a_file_sink_is_appended_to() {
+        let path = env::temp_dir().join(format!("rust-book-final-{}-access.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        AccessLog::open(&LogSink::File(path.clone())).unwrap().write("first").unwrap();
+        AccessLog::open(&LogSink::File(path.clone())).unwrap().write("second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::http::{Request, Response, StatusCode};
+use crate::websocket;
+
+/// HTTP Basic authentication (RFC 7617) against a single username and password.
+///
+/// Protects single routes with *guard*, or everything below a path with *middleware*.
+/// A request without the right `Authorization: Basic <base64>` header is answered with
+/// a 401 challenging the client for credentials. Basic sends them in the clear, so it's
+/// only safe over TLS or on a trusted network.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    realm: String,
+    // "username:password" in base64, what a client sends for the right credentials.
+    expected: String,
+}
+impl BasicAuth {
+    /// The realm names the protected area, browsers show it in their login dialog.
+    pub fn new(realm: &str, username: &str, password: &str) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_string(),
+            expected: websocket::base64(format!("{}:{}", username, password).as_bytes()),
+        }
+    }
+    /// Whether the request carries the right credentials.
+    pub fn is_authorized(&self, request: &Request) -> bool {
+        let given = request.headers.get("authorization").and_then(|value| {
+            let (scheme, credentials) = value.trim().split_once(' ')?;
+            Some(credentials.trim()).filter(|_| scheme.eq_ignore_ascii_case("basic"))
+        });
+        given.is_some_and(|given| constant_time_eq(given.as_bytes(), self.expected.as_bytes()))
+    }
+    /// The `401 Unauthorized` asking the client for credentials.
+    pub fn challenge(&self) -> Response {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        Response::new(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", &format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm))
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body("401 Unauthorized\n")
+    }
+    /// Wrap a handler so it only runs for authorized requests, .e.g.
+    /// `router.get("/admin", auth.guard(admin))`.
+    pub fn guard<F>(&self, handler: F) -> impl Fn(&Request) -> Response + Send + Sync + 'static
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            let auth = self.clone();
+            move |request| {
+                if auth.is_authorized(request) {
+                    handler(request)
+                } else {
+                    auth.challenge()
+                }
+            }
+        }
+    /// A middleware for *Router::use_middleware* protecting the path and everything
+    /// below it, routed or static, .e.g. `/admin` covers `/admin/users` but not
+    /// `/administrator`. `/` protects every request. The request path it checks is the
+    /// decoded and normalized one the router and the static files see, so neither
+    /// `//admin/users` nor `/%61dmin/users` get around it.
+    pub fn middleware(
+        &self,
+        prefix: &str,
+    ) -> impl Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static {
+        let auth = self.clone();
+        let prefix: Vec<String> = segments(prefix).map(str::to_string).collect();
+        move |request, next| {
+            let mut path = segments(&request.path);
+            let protected = prefix.iter().all(|segment| path.next() == Some(segment.as_str()));
+            if protected && !auth.is_authorized(request) {
+                auth.challenge()
+            } else {
+                next(request)
+            }
+        }
+    }
+}
+
+// compared a whole segment at a time, so "/admin" doesn't cover "/administrator".
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+// compares every byte whatever the first difference is, so the time taken doesn't tell
+// an attacker how much of a guess was right. only the length can be told apart.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use crate::gzip;
+use crate::http::{Request, Response, StatusCode};
+
+/// A file as it was last read from disk.
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub contents: Arc<Vec<u8>>,
+    // the modification time the contents were read at.
+    pub modified: SystemTime,
+    // a strong validator for the contents, quoted and ready for the "ETag" header.
+    pub etag: String,
+}
+
+/// Keeps file contents in memory, shared by every worker of the pool.
+///
+/// Each lookup still stats the file, and the file is only read again when its
+/// modification time changed since it was cached. Cloning the cache is cheap and
+/// the clones share their entries.
+#[derive(Clone, Default)]
+pub struct FileCache {
+    files: Arc<Mutex<HashMap<PathBuf, CachedFile>>>,
+}
+impl FileCache {
+    pub fn new() -> FileCache {
+        FileCache::default()
+    }
+    /// The contents of the file, read from disk if they aren't cached or went stale.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the file can't be stat'ed or read, the stale entry
+    /// is dropped in that case.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> io::Result<CachedFile> {
+        let path = path.as_ref();
+        let modified = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                self.files.lock().unwrap().remove(path);
+                return Err(e);
+            }
+        };
+        if let Some(file) = self.files.lock().unwrap().get(path) {
+            if file.modified == modified {
+                return Ok(file.clone());
+            }
+        }
+        // read without holding the lock, other workers shouldn't wait on the disk.
+        let contents = fs::read(path)?;
+        let file = CachedFile {
+            etag: etag(&contents),
+            contents: Arc::new(contents),
+            modified,
+        };
+        self.files.lock().unwrap().insert(path.to_path_buf(), file.clone());
+        Ok(file)
+    }
+    /// Read every file under the directory into the cache, .e.g. at startup so a file
+    /// that can't be read shows up right away rather than with the first request for
+    /// it. Files larger than *max_size* bytes are only opened, they're never cached.
+    /// Symlinks to directories aren't followed, they could lead back up the tree.
+    ///
+    /// Returns the number of files and their total size in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error for the first file or directory that can't be read, with
+    /// its path in the message.
+    pub fn preload<P: AsRef<Path>>(&self, dir: P, max_size: u64) -> io::Result<(usize, u64)> {
+        let with_path = |path: &Path, e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        let mut totals = (0, 0);
+        let mut dirs = vec![dir.as_ref().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).map_err(|e| with_path(&dir, e))? {
+                let path = entry.map_err(|e| with_path(&dir, e))?.path();
+                let is_link = fs::symlink_metadata(&path).map_err(|e| with_path(&path, e))?.file_type().is_symlink();
+                let metadata = fs::metadata(&path).map_err(|e| with_path(&path, e))?;
+                if metadata.is_dir() {
+                    if !is_link {
+                        dirs.push(path);
+                    }
+                    continue;
+                }
+                if metadata.len() > max_size {
+                    fs::File::open(&path).map_err(|e| with_path(&path, e))?;
+                } else {
+                    self.get(&path).map_err(|e| with_path(&path, e))?;
+                }
+                totals = (totals.0 + 1, totals.1 + metadata.len());
+            }
+        }
+        Ok(totals)
+    }
+}
+
+// the length and the CRC-32 of the contents, computed once per read from disk.
+pub(crate) fn etag(contents: &[u8]) -> String {
+    format!("\"{:x}-{:08x}\"", contents.len(), gzip::crc32(contents))
+}
+
+/// Keeps the responses of expensive handlers for a while, shared by every worker.
+///
+/// Only `GET` requests are cached, keyed by their path and query string, and only
+/// `200 OK` responses with their body in memory that don't say `no-store` or `private`
+/// themselves. A request with `Cache-Control: no-store` bypasses the cache, one with
+/// `no-cache` runs the handler and stores the fresh response. A handler whose response
+/// depends on anything else of the request (.e.g. a header or the session) shouldn't be
+/// cached. Beyond the capacity the least recently used entry is dropped.
+#[derive(Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Arc<Mutex<HashMap<String, CachedResponse>>>,
+}
+
+struct CachedResponse {
+    response: Response,
+    expires: Instant,
+    last_used: Instant,
+}
+
+impl ResponseCache {
+    /// Keep every response for *ttl*, and at most *capacity* of them.
+    pub fn new(ttl: Duration, capacity: usize) -> ResponseCache {
+        ResponseCache {
+            ttl,
+            capacity,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Wrap a handler so its responses are served from the cache while they're fresh,
+    /// .e.g. `router.get("/report", cache.cached(report))`.
+    pub fn cached<F>(&self, handler: F) -> impl Fn(&Request) -> Response + Send + Sync + 'static
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            let cache = self.clone();
+            move |request| {
+                let directives = request.headers.get_joined("cache-control").unwrap_or_default();
+                let has = |directive: &str| {
+                    directives.split(',').any(|part| part.trim().eq_ignore_ascii_case(directive))
+                };
+                if request.method != "GET" || has("no-store") {
+                    return handler(request);
+                }
+                let key = request.target.clone();
+                if !has("no-cache") {
+                    if let Some(response) = cache.lookup(&key) {
+                        return response;
+                    }
+                }
+                let response = handler(request);
+                if is_cacheable(&response) {
+                    cache.store(key, response.clone());
+                }
+                response
+            }
+        }
+    /// The number of responses stored, expired ones included until they're evicted.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+    fn lookup(&self, key: &str) -> Option<Response> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key).filter(|entry| entry.expires > now)?;
+        entry.last_used = now;
+        Some(entry.response.clone())
+    }
+    fn store(&self, key: String, response: Response) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            // expired entries go first, then the one nobody asked for the longest.
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.capacity {
+                let oldest = entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        let entry = CachedResponse { response, expires: now + self.ttl, last_used: now };
+        entries.insert(key, entry);
+    }
+}
+
+// a stream or a file can only be sent once, and a response may forbid being kept.
+fn is_cacheable(response: &Response) -> bool {
+    let forbidden = response.header_value("Cache-Control").is_some_and(|directives| {
+        directives.split(',').any(|part| {
+            let part = part.trim();
+            part.eq_ignore_ascii_case("no-store") || part.eq_ignore_ascii_case("private")
+        })
+    });
+    response.status == StatusCode::OK && response.chunks.is_none() && response.file.is_none() && !forbidden
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread;
+
+    // a fresh, empty directory for a test, removed again by the next run.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rust-book-final-{}-cache-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set_modified(path: &Path, modified: SystemTime) {
+        fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn a_file_is_read_again_once_its_mtime_changes() {
+        let path = temp_dir("mtime").join("page.html");
+        let then = SystemTime::now() - Duration::from_secs(60);
+        fs::write(&path, "old").unwrap();
+        set_modified(&path, then);
+        let cache = FileCache::new();
+        let first = cache.get(&path).unwrap();
+        assert_eq!(*first.contents, b"old");
+
+        // same mtime, so the cached contents are still served, by every clone.
+        fs::write(&path, "new").unwrap();
+        set_modified(&path, then);
+        assert_eq!(*cache.clone().get(&path).unwrap().contents, b"old");
+
+        set_modified(&path, then + Duration::from_secs(1));
+        let second = cache.get(&path).unwrap();
+        assert_eq!(*second.contents, b"new");
+        assert_ne!(first.modified, second.modified);
+        assert_eq!(*cache.clone().get(&path).unwrap().contents, b"new");
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cache.get(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    fn request(raw: &str) -> Request {
+        Request::parse(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn a_cached_handler_runs_once_within_the_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let runs = Arc::new(AtomicUsize::new(0));
+        let cache = ResponseCache::new(Duration::from_secs(60), 2);
+        let handler = cache.cached({
+            let runs = Arc::clone(&runs);
+            move |_| {
+                let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+                Response::new(StatusCode::OK).header("X-Run", &run.to_string()).body(format!("run {}", run))
+            }
+        });
+        let first = handler(&request("GET /report?year=2026 HTTP/1.1\r\n\r\n"));
+        let second = handler(&request("GET /report?year=2026 HTTP/1.1\r\n\r\n"));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(first.status, second.status);
+        assert_eq!(first.headers, second.headers);
+        assert_eq!(second.body, b"run 1");
+        assert_eq!(cache.len(), 1);
+
+        // another query, another method and a no-store request all run the handler.
+        handler(&request("GET /report?year=2025 HTTP/1.1\r\n\r\n"));
+        handler(&request("POST /report?year=2026 HTTP/1.1\r\nContent-Length: 0\r\n\r\n"));
+        let fresh = handler(&request("GET /report?year=2026 HTTP/1.1\r\nCache-Control: no-store\r\n\r\n"));
+        assert_eq!(fresh.body, b"run 4");
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        assert_eq!(handler(&request("GET /report?year=2026 HTTP/1.1\r\n\r\n")).body, b"run 1");
+    }
+
+    #[test]
+    fn the_least_recently_used_response_is_evicted() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 2);
+        let handler = cache.cached(|request| Response::new(StatusCode::OK).body(request.target.clone()));
+        handler(&request("GET /a HTTP/1.1\r\n\r\n"));
+        handler(&request("GET /b HTTP/1.1\r\n\r\n"));
+        thread::sleep(Duration::from_millis(2));
+        // */a* is more recent than */b* now.
+        handler(&request("GET /a HTTP/1.1\r\n\r\n"));
+        thread::sleep(Duration::from_millis(2));
+        handler(&request("GET /c HTTP/1.1\r\n\r\n"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.lookup("/a").is_some());
+        assert!(cache.lookup("/b").is_none());
+        assert!(cache.lookup("/c").is_some());
+
+        let expiring = ResponseCache::new(Duration::from_millis(0), 2);
+        expiring.cached(|_| Response::new(StatusCode::OK))(&request("GET /a HTTP/1.1\r\n\r\n"));
+        assert!(expiring.lookup("/a").is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a config file couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    // the file couldn't be read at all.
+    Io(io::Error),
+    // a line that isn't a comment, a table header or `key = value`.
+    Syntax { line: usize, message: String },
+    // a known key with a value of the wrong type or out of range.
+    Invalid { key: String, message: String },
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "can't read config file: {}", e),
+            ConfigError::Syntax { line, message } => write!(f, "config file line {}: {}", line, message),
+            ConfigError::Invalid { key, message } => write!(f, "config key `{}`: {}", key, message),
+        }
+    }
+}
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+/// A value on the right-hand side of a `key = value` line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+        }
+    }
+}
+
+/// The keys of a config file, as parsed by *parse*.
+#[derive(Debug, Default)]
+pub struct Table {
+    values: HashMap<String, Value>,
+}
+impl Table {
+    /// The string at *key*, `None` if the key isn't there.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError::Invalid` if the value isn't a string.
+    pub fn string(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(other) => Err(invalid(key, &format!("expected a string, found {}", other.type_name()))),
+        }
+    }
+    /// The non-negative integer at *key*, `None` if the key isn't there.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError::Invalid` if the value isn't an integer or is negative.
+    pub fn usize(&self, key: &str) -> Result<Option<usize>, ConfigError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(n)) if *n >= 0 => Ok(Some(*n as usize)),
+            Some(Value::Integer(n)) => Err(invalid(key, &format!("expected a non-negative integer, found {}", n))),
+            Some(other) => Err(invalid(key, &format!("expected an integer, found {}", other.type_name()))),
+        }
+    }
+    /// The keys present in the file, for rejecting unknown ones.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid { key: key.to_string(), message: message.to_string() }
+}
+
+/// Parse the flat subset of TOML a config file needs: `key = value` lines with
+/// basic strings, integers and booleans, and `#` comments.
+///
+/// # Errors
+///
+/// Returns a `ConfigError::Syntax` naming the first line that can't be parsed,
+/// including table headers and repeated keys.
+pub fn parse(source: &str) -> Result<Table, ConfigError> {
+    let mut table = Table::default();
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let syntax = |message: &str| ConfigError::Syntax { line, message: message.to_string() };
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+        if text.starts_with('[') {
+            return Err(syntax("tables aren't supported, every key goes at the top level"));
+        }
+        let (key, value) = match text.find('=') {
+            Some(i) => (text[..i].trim(), text[i + 1..].trim()),
+            None => return Err(syntax("expected `key = value`")),
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(syntax(&format!("invalid key `{}`", key)));
+        }
+        let value = parse_value(value).map_err(|message| syntax(&message))?;
+        if table.values.insert(key.to_string(), value).is_some() {
+            return Err(syntax(&format!("duplicate key `{}`", key)));
+        }
+    }
+    Ok(table)
+}
+
+// drop a trailing comment, a `#` inside a string is part of the string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    match text {
+        "" => return Err("missing value".to_string()),
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    if let Some(rest) = text.strip_prefix('"') {
+        return parse_string(rest).map(Value::String);
+    }
+    // TOML allows underscores between digits, .e.g. `1_048_576`.
+    let digits: String = text.chars().filter(|&c| c != '_').collect();
+    digits
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("unsupported value `{}`", text))
+}
+
+// the part after the opening quote, which must end with the closing one.
+fn parse_string(text: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return if chars.as_str().trim().is_empty() {
+                    Ok(value)
+                } else {
+                    Err("unexpected characters after the string".to_string())
+                };
+            }
+            '\\' => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                other => return Err(format!("unsupported escape `\\{}`", other.map_or(String::new(), String::from))),
+            },
+            _ => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_strings_and_integers_are_parsed() {
+        let source = "# the server\naddr = \"0.0.0.0:80\" # not # the end\n\nmax_body_size = 1_048_576\n";
+        let table = parse(source).unwrap();
+        assert_eq!(table.string("addr").unwrap().as_deref(), Some("0.0.0.0:80"));
+        assert_eq!(table.usize("max_body_size").unwrap(), Some(1 << 20));
+        assert_eq!(table.string("missing").unwrap(), None);
+        let e = table.usize("addr").unwrap_err();
+        assert_eq!(e.to_string(), "config key `addr`: expected an integer, found a string");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        for (source, message) in [
+            (
+                "addr = \"x\"\n[server]\n",
+                "config file line 2: tables aren't supported, every key goes at the top level",
+            ),
+            ("pool_size\n", "config file line 1: expected `key = value`"),
+            ("\naddr = \"unterminated\n", "config file line 2: unterminated string"),
+            ("pool_size = 4\npool_size = 8\n", "config file line 2: duplicate key `pool_size`"),
+            ("pool_size = four\n", "config file line 1: unsupported value `four`"),
+        ] {
+            assert_eq!(parse(source).unwrap_err().to_string(), message);
+        }
+    }
+}
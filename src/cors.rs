@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use crate::http::{Request, Response, StatusCode};
+
+/// Which origins may make cross-origin requests.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllowedOrigins {
+    // answered with `*`, can't be combined with credentials.
+    Any,
+    // compared case-insensitively against the "Origin" header, .e.g. "https://example.com".
+    List(Vec<String>),
+}
+
+/// The `Access-Control-Allow-*` headers the server adds for browser clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    pub origins: AllowedOrigins,
+    // listed in "Access-Control-Allow-Methods" of a preflight response.
+    pub methods: Vec<String>,
+    // request headers beyond the CORS-safelisted ones a client may send.
+    pub headers: Vec<String>,
+    // let the browser send cookies and credentials along, see *validate*.
+    pub credentials: bool,
+    // how long a browser may cache the preflight response, *None* leaves it to the browser.
+    pub max_age: Option<Duration>,
+}
+
+/// Why a `CorsConfig` can't be used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorsError {
+    // browsers reject a credentialed response with "Access-Control-Allow-Origin: *".
+    CredentialsWithAnyOrigin,
+}
+impl fmt::Display for CorsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorsError::CredentialsWithAnyOrigin => write!(f, "CORS credentials can't be allowed for any origin"),
+        }
+    }
+}
+impl Error for CorsError {}
+
+impl CorsConfig {
+    /// Check the config before the server starts.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CorsError::CredentialsWithAnyOrigin` if credentials are allowed
+    /// together with `AllowedOrigins::Any`, the spec forbids that combination.
+    pub fn validate(&self) -> Result<(), CorsError> {
+        if self.credentials && self.origins == AllowedOrigins::Any {
+            return Err(CorsError::CredentialsWithAnyOrigin);
+        }
+        Ok(())
+    }
+    // the value for "Access-Control-Allow-Origin", if the request's origin is allowed.
+    fn allow_origin(&self, request: &Request) -> Option<String> {
+        let origin = request.headers.get("origin")?;
+        match &self.origins {
+            AllowedOrigins::Any => Some("*".to_string()),
+            AllowedOrigins::List(origins) => origins
+                .iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .map(|_| origin.to_string()),
+        }
+    }
+    /// Answer a preflight request (an `OPTIONS` with `Origin` and
+    /// `Access-Control-Request-Method`) from an allowed origin with a 204.
+    ///
+    /// Any other request is left to the router, `None` is returned.
+    pub fn preflight(&self, request: &Request) -> Option<Response> {
+        if request.method != "OPTIONS" || !request.headers.contains_key("access-control-request-method") {
+            return None;
+        }
+        let origin = self.allow_origin(request)?;
+        let mut response = self
+            .with_origin(Response::new(StatusCode::NO_CONTENT), origin)
+            .header("Access-Control-Allow-Methods", &self.methods.join(", "));
+        if !self.headers.is_empty() {
+            response = response.header("Access-Control-Allow-Headers", &self.headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        Some(response)
+    }
+    /// Add the CORS headers to the response of a request from an allowed origin,
+    /// anything else (including a response that has them already) is returned as it is.
+    pub fn apply(&self, request: &Request, response: Response) -> Response {
+        if response.header_value("Access-Control-Allow-Origin").is_some() {
+            return response;
+        }
+        match self.allow_origin(request) {
+            Some(origin) => self.with_origin(response, origin),
+            None => response,
+        }
+    }
+    fn with_origin(&self, mut response: Response, origin: String) -> Response {
+        if origin != "*" {
+            // the header depends on the request's origin, caches must keep them apart.
+            response = response.header("Vary", "Origin");
+        }
+        response = response.header("Access-Control-Allow-Origin", &origin);
+        if self.credentials {
+            response = response.header("Access-Control-Allow-Credentials", "true");
+        }
+        response
+    }
+}
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            origins: AllowedOrigins::Any,
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &str) -> Request {
+        Request::parse(format!("{} /api HTTP/1.1\r\n{}\r\n", method, headers).as_bytes()).unwrap()
+    }
+
+    fn listed() -> CorsConfig {
+        CorsConfig {
+            origins: AllowedOrigins::List(vec!["https://example.com".to_string()]),
+            headers: vec!["Content-Type".to_string()],
+            max_age: Some(Duration::from_secs(600)),
+            ..CorsConfig::default()
+        }
+    }
+
+    #[test]
+    fn only_an_allowed_origin_gets_the_headers() {
+        let cors = listed();
+        let response = cors.apply(&request("GET", "Origin: https://EXAMPLE.com\r\n"), Response::new(StatusCode::OK));
+        assert_eq!(response.header_value("Access-Control-Allow-Origin"), Some("https://EXAMPLE.com"));
+        assert_eq!(response.header_value("Vary"), Some("Origin"));
+        assert_eq!(response.header_value("Access-Control-Allow-Credentials"), None);
+        let response = cors.apply(&request("GET", "Origin: https://evil.example\r\n"), Response::new(StatusCode::OK));
+        assert_eq!(response.header_value("Access-Control-Allow-Origin"), None);
+        // not a cross-origin request at all.
+        let response = cors.apply(&request("GET", ""), Response::new(StatusCode::OK));
+        assert_eq!(response.header_value("Access-Control-Allow-Origin"), None);
+
+        let any = CorsConfig::default();
+        let response = any.apply(&request("GET", "Origin: https://a.test\r\n"), Response::new(StatusCode::OK));
+        assert_eq!(response.header_value("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header_value("Vary"), None);
+    }
+
+    #[test]
+    fn a_preflight_from_an_allowed_origin_is_answered() {
+        let cors = listed();
+        let preflight = "Origin: https://example.com\r\nAccess-Control-Request-Method: POST\r\n";
+        let response = cors.preflight(&request("OPTIONS", preflight)).expect("a preflight response");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(response.header_value("Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(response.header_value("Access-Control-Allow-Methods"), Some("GET, HEAD, POST"));
+        assert_eq!(response.header_value("Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(response.header_value("Access-Control-Max-Age"), Some("600"));
+        let disallowed = "Origin: https://evil.example\r\nAccess-Control-Request-Method: POST\r\n";
+        assert!(cors.preflight(&request("OPTIONS", disallowed)).is_none());
+        // a plain OPTIONS is the router's business.
+        assert!(cors.preflight(&request("OPTIONS", "Origin: https://example.com\r\n")).is_none());
+    }
+
+    #[test]
+    fn credentials_need_a_list_of_origins() {
+        let wildcard = CorsConfig { credentials: true, ..CorsConfig::default() };
+        assert_eq!(wildcard.validate(), Err(CorsError::CredentialsWithAnyOrigin));
+        let cors = CorsConfig { credentials: true, ..listed() };
+        assert_eq!(cors.validate(), Ok(()));
+        let response = cors.apply(&request("GET", "Origin: https://example.com\r\n"), Response::new(StatusCode::OK));
+        assert_eq!(response.header_value("Access-Control-Allow-Credentials"), Some("true"));
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// "Jan" .. "Dec" and "Mon" .. "Sun", as used by the HTTP and log date formats.
+pub const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// A point in time broken down into UTC calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UtcDateTime {
+    pub year: i64,
+    // 1 ..= 12.
+    pub month: u32,
+    // 1 ..= 31.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+    // 0 is Monday.
+    pub weekday: u32,
+}
+impl UtcDateTime {
+    pub fn now() -> UtcDateTime {
+        UtcDateTime::from_system_time(SystemTime::now())
+    }
+    pub fn from_system_time(time: SystemTime) -> UtcDateTime {
+        // times before the epoch don't come up for a server, clamp them to it.
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs() as i64;
+        let days = seconds.div_euclid(86_400);
+        let of_day = seconds.rem_euclid(86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        UtcDateTime {
+            year,
+            month,
+            day,
+            hour: of_day / 3600,
+            minute: of_day % 3600 / 60,
+            second: of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+            // the epoch was a Thursday.
+            weekday: (days + 3).rem_euclid(7) as u32,
+        }
+    }
+    /// Formatted as ISO 8601, .e.g. `2026-10-14T08:05:09.123Z`.
+    pub fn to_iso8601(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millisecond
+        )
+    }
+    /// Formatted as an HTTP date (RFC 7231 IMF-fixdate), .e.g. `Wed, 14 Oct 2026 08:05:09 GMT`.
+    pub fn to_http_date(&self) -> String {
+        format!(
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[self.weekday as usize],
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+/// Parse an HTTP date in the IMF-fixdate format, .e.g. `Wed, 14 Oct 2026 08:05:09 GMT`.
+///
+/// The obsolete RFC 850 and asctime formats aren't understood and give `None`, like
+/// any other malformed date.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    // the weekday is redundant, it's only checked for being there.
+    let (_, rest) = date.trim().split_once(", ")?;
+    let parts: Vec<&str> = rest.split(' ').collect();
+    let (day, month, year, time) = match parts[..] {
+        [day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let year: i64 = year.parse().ok()?;
+    let fields: Vec<u64> = time.split(':').map(|field| field.parse().ok()).collect::<Option<_>>()?;
+    let (hour, minute, second) = match fields[..] {
+        [hour, minute, second] if hour < 24 && minute < 60 && second < 61 => (hour, minute, second),
+        _ => return None,
+    };
+    if !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+// days since 1970-01-01 to (year, month, day), after Howard Hinnant's "civil_from_days".
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// the inverse of *civil_from_days*.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_dates_are_formatted_and_parsed_back() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_791_965_109_123);
+        let date = UtcDateTime::from_system_time(time);
+        assert_eq!(date.to_http_date(), "Wed, 14 Oct 2026 08:05:09 GMT");
+        assert_eq!(date.to_iso8601(), "2026-10-14T08:05:09.123Z");
+        // whole seconds only.
+        assert_eq!(parse_http_date(&date.to_http_date()), Some(UNIX_EPOCH + Duration::from_secs(1_791_965_109)));
+        assert_eq!(UtcDateTime::from_system_time(UNIX_EPOCH).to_http_date(), "Thu, 01 Jan 1970 00:00:00 GMT");
+        // a leap second runs into the next day.
+        let leap = parse_http_date("Thu, 29 Feb 2024 23:59:60 GMT");
+        assert_eq!(leap, Some(UNIX_EPOCH + Duration::from_secs(1_709_251_200)));
+        let rfc850 = "Wednesday, 14-Oct-26 08:05:09 GMT";
+        for malformed in [rfc850, "Wed Oct 14 08:05:09 2026", "Wed, 14 Okt 2026 08:05:09 GMT"] {
+            assert_eq!(parse_http_date(malformed), None, "{}", malformed);
+        }
+    }
+}
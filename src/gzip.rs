@@ -0,0 +1,297 @@
+/*
+ * A small gzip (RFC 1952) encoder, so the server doesn't need any dependency.
+ *
+ * The body is a single deflate (RFC 1951) block using the fixed Huffman codes, with
+ * LZ77 matches found through a hash chain. That's not as tight as zlib, but text
+ * assets still shrink a lot.
+ */
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// how many earlier positions with the same hash are tried before giving up.
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compress the data into a complete gzip member.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // magic, deflate method, no flags, no mtime, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    // the size of the original data, modulo 2^32 as the format says.
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// The CRC-32 (IEEE) checksum gzip stores in its trailer.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// a raw deflate stream made of one final block with fixed Huffman codes.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes).
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        if length >= MIN_MATCH {
+            write_length(&mut bits, length);
+            write_distance(&mut bits, distance);
+            for p in pos..pos + length {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += length;
+        } else {
+            write_literal(&mut bits, u16::from(data[pos]));
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    // end of block.
+    write_literal(&mut bits, 256);
+    bits.finish()
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let key = u32::from(data[pos]) << 16 | u32::from(data[pos + 1]) << 8 | u32::from(data[pos + 2]);
+    (key.wrapping_mul(2_654_435_761) >> 17) as usize % HASH_SIZE
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH > data.len() {
+        return;
+    }
+    let h = hash(data, pos);
+    prev[pos % WINDOW_SIZE] = head[h];
+    head[h] = pos;
+}
+
+// walk the hash chain of *pos* and return the best (length, distance) found.
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let limit = (data.len() - pos).min(MAX_MATCH);
+    let (mut best_length, mut best_distance) = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+    let mut chain = 0;
+    while candidate != usize::MAX && chain < MAX_CHAIN {
+        let distance = pos - candidate;
+        if distance > WINDOW_SIZE - 1 {
+            break;
+        }
+        let length = (0..limit)
+            .take_while(|&i| data[candidate + i] == data[pos + i])
+            .count();
+        if length > best_length {
+            best_length = length;
+            best_distance = distance;
+            if length == limit {
+                break;
+            }
+        }
+        let next = prev[candidate % WINDOW_SIZE];
+        // an older slot that got overwritten points forward, that's the end of the chain.
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+        chain += 1;
+    }
+    (best_length, best_distance)
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    // the fixed literal/length code of RFC 1951, section 3.2.6.
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + (symbol - 144), 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + (symbol - 280), 8),
+    }
+}
+
+fn write_length(bits: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= length).unwrap();
+    write_literal(bits, 257 + index as u16);
+    bits.write((length - usize::from(LENGTH_BASE[index])) as u32, LENGTH_EXTRA[index]);
+}
+
+fn write_distance(bits: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE.iter().rposition(|&base| usize::from(base) <= distance).unwrap();
+    // distance codes are all five bits long.
+    bits.write_code(index as u16, 5);
+    bits.write((distance - usize::from(DISTANCE_BASE[index])) as u32, DISTANCE_EXTRA[index]);
+}
+
+// deflate packs bits starting from the least significant bit of each byte.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u8,
+}
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u8) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+    // Huffman codes are defined most significant bit first, so they go in reversed.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length);
+        self.write(u32::from(reversed), length);
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{self, Response, StatusCode};
+
+    // reads deflate's bits back in the order *BitWriter* wrote them.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u8) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+                value |= u32::from(bit) << i;
+                self.pos += 1;
+            }
+            value
+        }
+        // one bit after the other, most significant first, see *write_code*.
+        fn code(&mut self, length: u8) -> u32 {
+            (0..length).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+        fn literal(&mut self) -> u16 {
+            let code = self.code(7);
+            if code <= 0x17 {
+                return 256 + code as u16;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => (code - 0x30) as u16,
+                0xc0..=0xc7 => 280 + (code - 0xc0) as u16,
+                _ => 144 + ((code << 1 | self.bits(1)) - 0x190) as u16,
+            }
+        }
+    }
+
+    // just enough of an inflater for what *compress* writes: one block of fixed codes.
+    fn decompress(gzip: &[u8]) -> Vec<u8> {
+        assert_eq!(&gzip[..3], &[0x1f, 0x8b, 8]);
+        let mut bits = BitReader { data: &gzip[10..gzip.len() - 8], pos: 0 };
+        assert_eq!((bits.bits(1), bits.bits(2)), (1, 1), "a final block of fixed codes");
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let symbol = bits.literal();
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let index = usize::from(symbol - 257);
+                    let length = usize::from(LENGTH_BASE[index]) + bits.bits(LENGTH_EXTRA[index]) as usize;
+                    let index = bits.code(5) as usize;
+                    let distance = usize::from(DISTANCE_BASE[index]) + bits.bits(DISTANCE_EXTRA[index]) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+        let trailer = &gzip[gzip.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn compressed_data_round_trips() {
+        let text = "<p>the quick brown fox jumps over the lazy dog</p>\n".repeat(200);
+        let binary: Vec<u8> = (0..=255).cycle().take(4000).collect();
+        let long_run = vec![b'a'; 100_000];
+        for data in [text.as_bytes(), &binary, &long_run, b"", b"ab"] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed), data);
+        }
+        assert!(compress(text.as_bytes()).len() < text.len() / 10);
+    }
+
+    #[test]
+    fn the_checksum_is_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn a_gzipped_response_round_trips() {
+        let body = "hello, compressed world\n".repeat(100);
+        let headers = http::parse_headers("Accept-Encoding: gzip\r\n\r\n");
+        let response = Response::new(StatusCode::OK)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.clone())
+            .compress(&headers, 1024);
+        assert_eq!(response.header_value("Content-Encoding"), Some("gzip"));
+        assert_eq!(decompress(&response.body), body.as_bytes());
+    }
+}
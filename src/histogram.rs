@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counts of observed values in fixed buckets, updated without locking so every
+/// worker of the pool can share one.
+pub struct Histogram {
+    // the inclusive upper bound of each bucket, ascending. anything larger lands in
+    // the last element of *counts*, the "+Inf" bucket.
+    bounds: Vec<u64>,
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+impl Histogram {
+    /// A histogram with the given bucket bounds, sorted and deduplicated.
+    pub fn new(mut bounds: Vec<u64>) -> Histogram {
+        bounds.sort_unstable();
+        bounds.dedup();
+        let counts = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Histogram { bounds, counts, sum: AtomicU64::new(0) }
+    }
+    /// Buckets at `start`, `2 * start`, `4 * start` and so on, *buckets* of them.
+    pub fn powers_of_two(start: u64, buckets: u32) -> Histogram {
+        Histogram::new((0..buckets).filter_map(|i| start.checked_shl(i)).collect())
+    }
+    pub fn observe(&self, value: u64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+    /// The number of values observed in each bucket (not cumulative), the last one
+    /// being everything above the highest bound.
+    pub fn counts(&self) -> Vec<u64> {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect()
+    }
+    /// Append the histogram in the Prometheus text format, .e.g.
+    /// `name_bucket{le="64"} 3` with cumulative counts, then `name_sum` and `name_count`.
+    pub fn render(&self, name: &str, out: &mut String) {
+        out.push_str(&format!("# TYPE {} histogram\n", name));
+        let mut total = 0;
+        for (i, count) in self.counts().into_iter().enumerate() {
+            total += count;
+            let le = self.bounds.get(i).map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, le, total));
+        }
+        out.push_str(&format!("{}_sum {}\n", name, self.sum.load(Ordering::Relaxed)));
+        out.push_str(&format!("{}_count {}\n", name, total));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_land_in_the_first_bucket_that_holds_them() {
+        let histogram = Histogram::powers_of_two(1, 4);
+        for value in [0, 1, 2, 3, 8, 9, 1000] {
+            histogram.observe(value);
+        }
+        // 1, 2, 4, 8 and everything above.
+        assert_eq!(histogram.counts(), [2, 1, 1, 1, 2]);
+        let mut out = String::new();
+        histogram.render("size", &mut out);
+        let expected = "# TYPE size histogram\nsize_bucket{le=\"1\"} 2\nsize_bucket{le=\"2\"} 3\n\
+                        size_bucket{le=\"4\"} 4\nsize_bucket{le=\"8\"} 5\nsize_bucket{le=\"+Inf\"} 7\n\
+                        size_sum 1023\nsize_count 7\n";
+        assert_eq!(out, expected);
+        // unsorted and repeated bounds are tidied up.
+        assert_eq!(Histogram::new(vec![8, 2, 8]).counts().len(), 3);
+    }
+}
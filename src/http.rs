@@ -0,0 +1,1758 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::date::UtcDateTime;
+use crate::gzip;
+use crate::json::ToJson;
+use crate::log::{log, Level};
+use crate::multipart::{self, Part};
+
+// the verbs the server knows how to answer, also listed in the "Allow" header of a 405.
+pub const SUPPORTED_METHODS: [&str; 6] = ["GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS"];
+pub const SUPPORTED_VERSIONS: [&str; 2] = ["HTTP/1.0", "HTTP/1.1"];
+
+/*
+ * HTTP Request Format:
+ *
+ * Method Request-URI HTTP-Version CRLF
+ * headers CRLF
+ * message-body
+ *
+ * .e.g. GET / HTTP/1.1\r\n.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    // percent-decoded, without the query string (see *query*). routes and static files
+    // are matched against it.
+    pub path: String,
+    // the path and query string as they came in the request line, not decoded.
+    pub target: String,
+    pub version: String,
+    // percent-decoded query parameters, the first value wins for a repeated key.
+    pub query: HashMap<String, String>,
+    // every query parameter in the order sent, see *query_all*.
+    pub query_pairs: Vec<(String, String)>,
+    // a repeated header keeps one value per line, *HeaderMap::get_joined* joins them.
+    pub headers: HeaderMap,
+    // captured from the path by the matched route, .e.g. "id" for "/users/:id".
+    pub params: HashMap<String, String>,
+    // exactly "Content-Length" bytes, filled in by the server after the head was parsed.
+    pub body: Vec<u8>,
+    // the far end of the connection, filled in by the server (*None* for an in-memory stream).
+    pub peer: Option<SocketAddr>,
+    // who sent the request: the peer's address, or the first "X-Forwarded-For" entry
+    // when the server trusts its proxy.
+    pub client_ip: Option<IpAddr>,
+    // ties the log lines of the request together, filled in by the server from
+    // "X-Request-Id" or generated. empty for a request that didn't come through it.
+    pub id: String,
+    // the session of the client, filled in by the server when it keeps sessions, see
+    // *SessionStore*.
+    pub session_id: Option<String>,
+    // when the answer is due, filled in by the server as the request arrives when it has
+    // a *request_budget*, see *time_remaining*.
+    pub deadline: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    Malformed,
+    UnsupportedVersion,
+    // the path can't be percent-decoded, see *decode_path*.
+    InvalidPath(DecodeError),
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Malformed => write!(f, "malformed request line"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::InvalidPath(e) => write!(f, "invalid path: {}", e),
+        }
+    }
+}
+impl Error for ParseError {}
+
+/// The `Content-Type` of a request, see *Request::content_type*.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+    // lowercased, .e.g. "application/json" of "Application/JSON; charset=UTF-8".
+    pub media_type: String,
+    // lowercased as well, .e.g. "utf-8".
+    pub charset: Option<String>,
+    // what separates the parts of a "multipart/*" body, kept as sent.
+    pub boundary: Option<String>,
+}
+impl ContentType {
+    /// Parse a header value like `application/json; charset=utf-8`, `None` if it
+    /// doesn't start with a `type/subtype`.
+    pub fn parse(value: &str) -> Option<ContentType> {
+        let media_type = value.split(';').next()?.trim().to_ascii_lowercase();
+        match media_type.split_once('/') {
+            Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() && !subtype.contains('/') => {}
+            _ => return None,
+        }
+        let boundary = if media_type.starts_with("multipart/") {
+            multipart::parameter(value, "boundary").filter(|boundary| !boundary.is_empty())
+        } else {
+            None
+        };
+        Some(ContentType {
+            charset: multipart::parameter(value, "charset").map(|charset| charset.to_ascii_lowercase()),
+            boundary,
+            media_type,
+        })
+    }
+    /// Whether this is the media type, in any case, .e.g. `application/json`.
+    pub fn is(&self, media_type: &str) -> bool {
+        self.media_type.eq_ignore_ascii_case(media_type)
+    }
+}
+
+/// One entry of an `Accept` header, see *parse_accept*.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    // lowercased, .e.g. "text/html", "text/*" or "*/*".
+    pub media_type: String,
+    // from 0 (not acceptable at all) to 1, which is what an entry without "q" gets.
+    pub quality: f32,
+}
+impl MediaRange {
+    /// Whether a media type falls into the range, .e.g. `text/html; charset=utf-8` into
+    /// `text/*`. Parameters other than `q` don't count.
+    pub fn matches(&self, media_type: &str) -> bool {
+        let media_type = media_type.split(';').next().unwrap_or("").trim();
+        match self.media_type.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((kind, "*")) => media_type
+                .split_once('/')
+                .is_some_and(|(other, _)| other.eq_ignore_ascii_case(kind)),
+            _ => media_type.eq_ignore_ascii_case(&self.media_type),
+        }
+    }
+    // a more specific range overrides a less specific one, see RFC 7231 section 5.3.2.
+    fn specificity(&self) -> u8 {
+        match self.media_type.split_once('/') {
+            Some(("*", "*")) => 0,
+            Some((_, "*")) => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl Request {
+    /// Parse the request line at the start of the raw bytes read from a connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ParseError::Malformed` if the buffer is empty or the request line
+    /// isn't exactly a method, a path and a version separated by whitespace, and
+    /// `ParseError::UnsupportedVersion` for anything but HTTP/1.0 and HTTP/1.1.
+    /// `ParseError::InvalidPath` if the path can't be percent-decoded.
+    pub fn parse(buffer: &[u8]) -> Result<Request, ParseError> {
+        let raw = String::from_utf8_lossy(buffer);
+        // only the first line is the request line, headers follow.
+        let (line, rest) = match raw.find("\r\n") {
+            Some(end) => (&raw[..end], &raw[end + 2..]),
+            None => (&raw[..], ""),
+        };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (method, target, version) = match parts[..] {
+            [method, target, version] => (method, target, version),
+            _ => return Err(ParseError::Malformed),
+        };
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(ParseError::UnsupportedVersion);
+        }
+        let (path, query_string) = match target.split_once('?') {
+            Some((path, query_string)) => (path, query_string),
+            None => (target, ""),
+        };
+        let path = decode_path(path).map_err(ParseError::InvalidPath)?;
+        let query_pairs = parse_query(query_string);
+        let mut query = HashMap::new();
+        for (key, value) in &query_pairs {
+            query.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        Ok(Request {
+            method: method.to_string(),
+            path,
+            target: target.to_string(),
+            version: version.to_string(),
+            query,
+            query_pairs,
+            headers: parse_headers(rest),
+            params: HashMap::new(),
+            body: Vec::new(),
+            peer: None,
+            client_ip: None,
+            id: String::new(),
+            session_id: None,
+            deadline: None,
+        })
+    }
+    pub fn is_supported_method(&self) -> bool {
+        SUPPORTED_METHODS.contains(&self.method.as_str())
+    }
+    /// How much of the server's request budget is left, zero once it's used up. `None`
+    /// if the request has no deadline. A slow handler can check this before .e.g. a
+    /// query nobody would wait for anymore.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+    /// The parsed `Content-Type` header, `None` if there's none or it isn't a media type.
+    pub fn content_type(&self) -> Option<ContentType> {
+        ContentType::parse(self.headers.get("content-type")?)
+    }
+    /// Pick the media type the client prefers out of the ones the handler can produce,
+    /// .e.g. `request.preferred(&["application/json", "text/html"])`. A tie goes to
+    /// the one offered first, and so does a request without an `Accept` header.
+    ///
+    /// `None` if the client accepts none of them, the handler can answer with a
+    /// `406 Not Acceptable` then.
+    pub fn preferred<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let accept = match self.headers.get_joined("accept").as_deref().map(parse_accept) {
+            Some(accept) if !accept.is_empty() => accept,
+            _ => return offered.first().copied(),
+        };
+        let quality = |media_type: &str| {
+            let mut best: Option<&MediaRange> = None;
+            for range in accept.iter().filter(|range| range.matches(media_type)) {
+                if best.is_none_or(|best| range.specificity() > best.specificity()) {
+                    best = Some(range);
+                }
+            }
+            best.map_or(0.0, |range| range.quality)
+        };
+        let mut preferred = None;
+        let mut preferred_quality = 0.0;
+        for &media_type in offered {
+            let quality = quality(media_type);
+            if quality > preferred_quality {
+                preferred = Some(media_type);
+                preferred_quality = quality;
+            }
+        }
+        preferred
+    }
+    /// The parts of a `multipart/form-data` body, `None` if the request has another
+    /// content type (or no boundary), see *multipart::parse_multipart*.
+    pub fn multipart(&self) -> Option<Vec<Part>> {
+        let boundary = multipart::boundary(self.headers.get("content-type")?)?;
+        Some(multipart::parse_multipart(&self.body, &boundary))
+    }
+    /// Every value sent for a query parameter, .e.g. both `a` and `b` for `?tag=a&tag=b`.
+    pub fn query_all(&self, key: &str) -> Vec<&str> {
+        self.query_pairs
+            .iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+    /// The value of a cookie the client sent along, .e.g. `abc` for `name` of
+    /// `Cookie: theme=dark; name=abc`.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        // one "Cookie" line is the norm, HTTP/2 proxies may split it up.
+        let mut cookies = self.headers.get_all("cookie").into_iter().flat_map(|line| line.split(';'));
+        cookies.find_map(|cookie| {
+            let (key, value) = cookie.split_once('=')?;
+            if key.trim() == name {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })
+    }
+    /// The original client of a proxied request, the first entry of `X-Forwarded-For`.
+    ///
+    /// Anyone can send that header, so only trust it behind a proxy that sets it.
+    pub fn forwarded_for(&self) -> Option<IpAddr> {
+        let forwarded_for = self.headers.get("x-forwarded-for")?;
+        forwarded_for.split(',').next()?.trim().parse().ok()
+    }
+    /// Whether the client wants the connection kept open after this request.
+    ///
+    /// An explicit `Connection` header wins, otherwise HTTP/1.1 defaults to keep-alive
+    /// and older versions to close.
+    pub fn keep_alive(&self) -> bool {
+        if let Some(connection) = self.headers.get_joined("connection") {
+            for token in connection.split(',') {
+                let token = token.trim();
+                if token.eq_ignore_ascii_case("close") {
+                    return false;
+                }
+                if token.eq_ignore_ascii_case("keep-alive") {
+                    return true;
+                }
+            }
+        }
+        self.version == "HTTP/1.1"
+    }
+}
+
+/// How far *HeadParser* got with the bytes it was given.
+#[derive(Debug, PartialEq)]
+pub enum HeadStatus {
+    /// The blank line ending the head hasn't arrived yet.
+    NeedMore,
+    /// The whole head is there, the body starts at the offset.
+    Complete(Box<Request>, usize),
+    /// The request line is no good, there's no point in reading the rest.
+    Error(ParseError),
+}
+
+/// Finds the end of a request head in a buffer that grows one read at a time, however
+/// the bytes are split up (.e.g. between the CR and the LF of a line ending).
+///
+/// The parser only remembers how far it got, the caller keeps the buffer and hands it
+/// over again after every read. Already scanned bytes aren't looked at twice, and the
+/// request line is checked as soon as it's complete, so garbage is refused without
+/// waiting for a head that may never end.
+#[derive(Debug, Clone, Default)]
+pub struct HeadParser {
+    // bytes of the buffer scanned so far.
+    scanned: usize,
+    state: Scan,
+    // the request line ended, and was fine.
+    line_checked: bool,
+    // set once the head ended, where the body starts.
+    head_end: Option<usize>,
+    error: Option<ParseError>,
+}
+
+// the line endings seen right before the next byte.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Scan {
+    #[default]
+    Text,
+    Cr,
+    CrLf,
+    CrLfCr,
+}
+
+impl HeadParser {
+    pub fn new() -> HeadParser {
+        HeadParser::default()
+    }
+    /// Scan the bytes appended to *buffer* since the last call. The buffer has to start
+    /// with the same bytes every time, until *reset*.
+    pub fn advance(&mut self, buffer: &[u8]) -> HeadStatus {
+        if let Some(e) = self.error {
+            return HeadStatus::Error(e);
+        }
+        if self.head_end.is_none() {
+            for (i, &byte) in buffer.iter().enumerate().skip(self.scanned) {
+                self.state = match (self.state, byte) {
+                    (Scan::CrLfCr, b'\n') => {
+                        self.head_end = Some(i + 1);
+                        break;
+                    }
+                    (Scan::Cr, b'\n') => {
+                        if !self.line_checked {
+                            self.line_checked = true;
+                            if let Err(e) = Request::parse(&buffer[..i - 1]) {
+                                self.error = Some(e);
+                                return HeadStatus::Error(e);
+                            }
+                        }
+                        Scan::CrLf
+                    }
+                    (Scan::CrLf, b'\r') => Scan::CrLfCr,
+                    (_, b'\r') => Scan::Cr,
+                    _ => Scan::Text,
+                };
+            }
+            self.scanned = buffer.len();
+        }
+        let head_end = match self.head_end {
+            Some(head_end) => head_end,
+            None => return HeadStatus::NeedMore,
+        };
+        match Request::parse(&buffer[..head_end]) {
+            Ok(request) => HeadStatus::Complete(Box::new(request), head_end),
+            Err(e) => HeadStatus::Error(e),
+        }
+    }
+    /// Start over with the next request, once the caller dropped the current one from
+    /// the front of its buffer.
+    pub fn reset(&mut self) {
+        *self = HeadParser::default();
+    }
+}
+
+/// How much of a chunked request body *decode_chunked* found in the bytes it was given.
+#[derive(Debug, PartialEq)]
+pub enum ChunkedStatus {
+    /// The last chunk (or the blank line after the trailers) hasn't arrived yet.
+    NeedMore,
+    /// The reassembled body, and how many bytes it took up, trailers included.
+    Complete(Vec<u8>, usize),
+    /// A chunk size isn't hex, or a chunk doesn't end with CRLF.
+    Invalid,
+}
+
+/// Decode a body sent with `Transfer-Encoding: chunked` (RFC 9112, section 7.1), as
+/// much of it as came in after the head, .e.g. `4\r\nWiki\r\n0\r\n\r\n` is `Wiki`.
+///
+/// Chunk extensions are ignored, and so are the trailer fields after the last chunk.
+/// Whatever follows the body in the buffer (a pipelined request) is left alone.
+pub fn decode_chunked(data: &[u8]) -> ChunkedStatus {
+    ChunkedDecoder::new().advance(data)
+}
+
+/// *decode_chunked* for a body that grows one read at a time.
+///
+/// Like *HeadParser* it only remembers how far it got, the caller keeps the buffer (from
+/// the first byte of the body on) and hands it over again after every read. A chunk is
+/// decoded once it's all there, so a long body isn't decoded over and over again.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkedDecoder {
+    // the chunks decoded so far.
+    body: Vec<u8>,
+    // where the next chunk size line (or trailer line) starts.
+    pos: usize,
+    // the last chunk was there, only trailer fields are left.
+    trailers: bool,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> ChunkedDecoder {
+        ChunkedDecoder::default()
+    }
+    /// Decode the chunks completed since the last call. The buffer has to start with the
+    /// same bytes every time, until *reset*.
+    pub fn advance(&mut self, data: &[u8]) -> ChunkedStatus {
+        let line_end = |from: usize| data[from..].windows(2).position(|pair| pair == b"\r\n").map(|end| from + end);
+        while !self.trailers {
+            let end = match line_end(self.pos) {
+                Some(end) => end,
+                None => return ChunkedStatus::NeedMore,
+            };
+            // "1a;name=value", only the size matters.
+            let line = String::from_utf8_lossy(&data[self.pos..end]);
+            let digits = line.split(';').next().unwrap_or("").trim();
+            let size = match usize::from_str_radix(digits, 16) {
+                // a sign is no hex digit, even if *from_str_radix* takes it.
+                Ok(size) if !digits.starts_with('+') => size,
+                _ => return ChunkedStatus::Invalid,
+            };
+            if size == 0 {
+                self.pos = end + 2;
+                self.trailers = true;
+                break;
+            }
+            // a size near *usize::MAX* must not wrap the offsets, .e.g. "ffffffffffffffed".
+            let chunk_end = match (end + 2).checked_add(size) {
+                Some(chunk_end) if chunk_end.checked_add(2).is_some() => chunk_end,
+                _ => return ChunkedStatus::Invalid,
+            };
+            // the size line is read again next time, the chunk it announces isn't all here.
+            if data.len() < chunk_end + 2 {
+                return ChunkedStatus::NeedMore;
+            }
+            if &data[chunk_end..chunk_end + 2] != b"\r\n" {
+                return ChunkedStatus::Invalid;
+            }
+            self.body.extend_from_slice(&data[end + 2..chunk_end]);
+            self.pos = chunk_end + 2;
+        }
+        // trailer fields up to a blank line.
+        loop {
+            let end = match line_end(self.pos) {
+                Some(end) => end,
+                None => return ChunkedStatus::NeedMore,
+            };
+            let blank = end == self.pos;
+            self.pos = end + 2;
+            if blank {
+                return ChunkedStatus::Complete(std::mem::take(&mut self.body), self.pos);
+            }
+        }
+    }
+    /// Start over with the next request's body.
+    pub fn reset(&mut self) {
+        *self = ChunkedDecoder::default();
+    }
+}
+
+/// Split a query string like `q=rust&page=2` into decoded key/value pairs.
+///
+/// A key without `=` (.e.g. `?flag`) gets an empty value, just like `?flag=`.
+pub fn parse_query(query_string: &str) -> Vec<(String, String)> {
+    query_string
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query_component(key), decode_query_component(value))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    // a "%" not followed by two hex digits, .e.g. "%2" or "%ZZ".
+    InvalidEscape,
+    // the decoded bytes aren't UTF-8.
+    InvalidUtf8,
+    // "%2F" or "%00" in a path segment, see *decode_path*.
+    EscapedSeparator,
+    // more ".." segments than there are segments before them, .e.g. "/a/../../etc".
+    EscapesRoot,
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidEscape => write!(f, "invalid percent escape"),
+            DecodeError::InvalidUtf8 => write!(f, "percent-decoded text isn't valid UTF-8"),
+            DecodeError::EscapedSeparator => write!(f, "escaped separator in a path segment"),
+            DecodeError::EscapesRoot => write!(f, "path climbs above the root"),
+        }
+    }
+}
+impl Error for DecodeError {}
+
+/// Percent-decode a request path (or a segment of it), .e.g. `/my%20file.html` to
+/// `/my file.html`.
+///
+/// Unlike in a query string, a `+` stays a `+`.
+///
+/// # Errors
+///
+/// Returns `DecodeError::InvalidEscape` for a `%` that isn't followed by two hex
+/// digits, and `DecodeError::InvalidUtf8` if the decoded bytes aren't UTF-8.
+pub fn percent_decode(encoded: &str) -> Result<String, DecodeError> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = bytes
+                .get(i + 1..i + 3)
+                .and_then(|pair| hex_pair(pair[0], pair[1]))
+                .ok_or(DecodeError::InvalidEscape)?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| DecodeError::InvalidUtf8)
+}
+
+// the path of a request as it's routed, looked up and checked by middlewares, decoded a
+// segment at a time. an escaped "/" (or NUL) would smuggle several segments into one, it's
+// refused like a broken escape. empty and "." segments are dropped and ".." removes the
+// one before it, so "//admin/./x" or "/a/../admin/x" are just "/admin/x" to everyone. a
+// ".." with nothing left to remove is refused too, it's a traversal attempt rather than
+// a path. a trailing slash is kept.
+fn decode_path(path: &str) -> Result<String, DecodeError> {
+    // .e.g. "*" of "OPTIONS *".
+    if !path.starts_with('/') {
+        return percent_decode(path);
+    }
+    let mut segments: Vec<String> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path[1..].split('/') {
+        let segment = percent_decode(segment)?;
+        if segment.contains(['/', '\0']) {
+            return Err(DecodeError::EscapedSeparator);
+        }
+        trailing_slash = true;
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(DecodeError::EscapesRoot);
+                }
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+    let mut decoded = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        decoded.push('/');
+    }
+    Ok(decoded)
+}
+
+// "+" stands for a space in a query string, and a broken "%" escape is kept as it is.
+fn decode_query_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes.get(i + 1..i + 3).and_then(|pair| hex_pair(pair[0], pair[1])) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_pair(high: u8, low: u8) -> Option<u8> {
+    let digit = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    Some(digit(high)? << 4 | digit(low)?)
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986, so the
+/// result can be used as a single path segment or query component.
+pub fn percent_encode(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Escape the characters that have a meaning in HTML text and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Header fields in the order they were added, looked up by name case-insensitively,
+/// so `Content-Type` and `content-type` are the same header. A name can have several
+/// values, .e.g. one per cookie a response sets with `Set-Cookie`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HeaderMap {
+    // names kept as given, that's how they go on the wire.
+    entries: Vec<(String, String)>,
+}
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+    /// The first value of the header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// Every value of the header, in the order they were added.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+    /// Every value of the header in one, the way a repeated header is meant to be read
+    /// (RFC 9110 section 5.3): joined with commas, or with semicolons for `Cookie`.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values = self.get_all(name);
+        if values.is_empty() {
+            return None;
+        }
+        let separator = if name.eq_ignore_ascii_case("cookie") { "; " } else { ", " };
+        Some(values.join(separator))
+    }
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    /// Set the header to this one value, in the place of its first one if it had any.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.entries.iter().position(|(header, _)| header.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                self.entries[index].1 = value.to_string();
+                let mut seen = 0;
+                self.entries.retain(|(header, _)| {
+                    seen += usize::from(header.eq_ignore_ascii_case(name));
+                    seen <= 1 || !header.eq_ignore_ascii_case(name)
+                });
+            }
+            None => self.append(name, value),
+        }
+    }
+    /// Add a value to the header, after the ones it already has.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+    /// Drop every value of the header, returning the first one.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.get(name).map(str::to_string);
+        self.entries.retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        first
+    }
+    /// Keep only the headers the predicate returns `true` for, given each name and value.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&str, &str) -> bool, {
+            self.entries.retain(|(name, value)| f(name, value));
+        }
+    /// Every name and value, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+    /// The number of values, a header with two of them counts twice.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Parse the header lines that follow the request line into a map.
+///
+/// Parsing stops at the blank line separating the headers from the body. A repeated
+/// header keeps one value per line, *HeaderMap::get_joined* has them all in one.
+pub fn parse_headers(raw: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for line in raw.split("\r\n") {
+        if line.is_empty() {
+            break;
+        }
+        // lines without a colon aren't headers, skip them.
+        if let Some((name, value)) = line.split_once(':') {
+            headers.append(name.trim(), value.trim());
+        }
+    }
+    headers
+}
+
+/// An HTTP status code, .e.g. `StatusCode::NOT_FOUND`, written with its reason phrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+// every code with a constant, and the reason phrase RFC 9110 gives it.
+macro_rules! status_codes {
+    ($($name:ident = $code:expr, $reason:expr;)*) => {
+        impl StatusCode {
+            $(pub const $name: StatusCode = StatusCode($code);)*
+
+            /// The standard reason phrase, .e.g. `Not Found` for 404, `None` for a code
+            /// that has no constant here.
+            pub fn canonical_reason(self) -> Option<&'static str> {
+                match self.0 {
+                    $($code => Some($reason),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+status_codes! {
+    CONTINUE = 100, "Continue";
+    SWITCHING_PROTOCOLS = 101, "Switching Protocols";
+    OK = 200, "OK";
+    CREATED = 201, "Created";
+    ACCEPTED = 202, "Accepted";
+    NO_CONTENT = 204, "No Content";
+    PARTIAL_CONTENT = 206, "Partial Content";
+    MOVED_PERMANENTLY = 301, "Moved Permanently";
+    FOUND = 302, "Found";
+    SEE_OTHER = 303, "See Other";
+    NOT_MODIFIED = 304, "Not Modified";
+    TEMPORARY_REDIRECT = 307, "Temporary Redirect";
+    PERMANENT_REDIRECT = 308, "Permanent Redirect";
+    BAD_REQUEST = 400, "Bad Request";
+    UNAUTHORIZED = 401, "Unauthorized";
+    FORBIDDEN = 403, "Forbidden";
+    NOT_FOUND = 404, "Not Found";
+    METHOD_NOT_ALLOWED = 405, "Method Not Allowed";
+    NOT_ACCEPTABLE = 406, "Not Acceptable";
+    REQUEST_TIMEOUT = 408, "Request Timeout";
+    CONFLICT = 409, "Conflict";
+    LENGTH_REQUIRED = 411, "Length Required";
+    PRECONDITION_FAILED = 412, "Precondition Failed";
+    PAYLOAD_TOO_LARGE = 413, "Payload Too Large";
+    URI_TOO_LONG = 414, "URI Too Long";
+    UNSUPPORTED_MEDIA_TYPE = 415, "Unsupported Media Type";
+    RANGE_NOT_SATISFIABLE = 416, "Range Not Satisfiable";
+    EXPECTATION_FAILED = 417, "Expectation Failed";
+    UPGRADE_REQUIRED = 426, "Upgrade Required";
+    TOO_MANY_REQUESTS = 429, "Too Many Requests";
+    REQUEST_HEADER_FIELDS_TOO_LARGE = 431, "Request Header Fields Too Large";
+    INTERNAL_SERVER_ERROR = 500, "Internal Server Error";
+    NOT_IMPLEMENTED = 501, "Not Implemented";
+    BAD_GATEWAY = 502, "Bad Gateway";
+    SERVICE_UNAVAILABLE = 503, "Service Unavailable";
+    GATEWAY_TIMEOUT = 504, "Gateway Timeout";
+    HTTP_VERSION_NOT_SUPPORTED = 505, "HTTP Version Not Supported";
+}
+impl StatusCode {
+    /// The status code with the given number, `None` outside of 100 to 599.
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        if (100..600).contains(&code) {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+    /// The line a response starts with, .e.g. `HTTP/1.1 404 Not Found\r\n`. The reason
+    /// phrase is left empty for a code without a canonical one, as the grammar allows.
+    pub fn status_line(self, version: &str) -> String {
+        format!("{} {} {}\r\n", version, self.0, self.canonical_reason().unwrap_or(""))
+    }
+    /// A 3xx code, .e.g. `MOVED_PERMANENTLY` or `NOT_MODIFIED`.
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.0)
+    }
+    // a 204 or a 304 never carries a body.
+    fn is_bodyless(self) -> bool {
+        self.0 < 200 || self == StatusCode::NO_CONTENT || self == StatusCode::NOT_MODIFIED
+    }
+}
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.canonical_reason() {
+            Some(reason) => write!(f, "{} {}", self.0, reason),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/*
+ * HTTP Response Format:
+ *
+ * HTTP-Version Status-Code Reason-Phrase CRLF
+ * headers CRLF
+ * message-body
+ *
+ * .e.g. HTTP/1.1 200 OK\r\n\r\n.
+ * - CRLF stands for carriage return and line feed (\r\n).
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    // "HTTP/1.1" unless downgraded with *for_http10*.
+    pub version: &'static str,
+    pub status: StatusCode,
+    // kept in insertion order, that's the order they go on the wire.
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // sent instead of *body* with "Transfer-Encoding: chunked", see *Response::streaming*.
+    pub chunks: Option<Chunks>,
+    // sent instead of *body*, read from disk while it's being written out.
+    pub file: Option<FileBody>,
+}
+
+/// The body of a streaming response, produced chunk by chunk while it's being sent.
+///
+/// Clones share the same iterator, whichever writes the response first consumes it.
+#[derive(Clone)]
+pub struct Chunks(Arc<Mutex<Box<dyn Iterator<Item = Vec<u8>> + Send>>>);
+impl fmt::Debug for Chunks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chunks(..)")
+    }
+}
+impl PartialEq for Chunks {
+    fn eq(&self, other: &Chunks) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+// the size of the pieces a *FileBody* is sent in.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The body of a response sent straight from a file, a piece at a time, so memory
+/// stays flat whatever the size of the file.
+///
+/// Clones share the open file, whichever writes the response first consumes it.
+#[derive(Clone)]
+pub struct FileBody {
+    state: Arc<Mutex<FileState>>,
+    // what goes out as "Content-Length", fixed when the body is opened.
+    pub length: u64,
+}
+struct FileState {
+    file: File,
+    remaining: u64,
+}
+impl fmt::Debug for FileBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileBody({} bytes)", self.length)
+    }
+}
+impl PartialEq for FileBody {
+    fn eq(&self, other: &FileBody) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+impl FileBody {
+    /// Open the file to send *length* bytes of it, starting *offset* bytes in.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the file can't be opened or seeked.
+    pub fn open<P: AsRef<Path>>(path: P, offset: u64, length: u64) -> io::Result<FileBody> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let state = FileState { file, remaining: length };
+        Ok(FileBody { state: Arc::new(Mutex::new(state)), length })
+    }
+    /// Read the next part of the body into *buf*, `Ok(0)` once all of it was read. More
+    /// than *length* is never read, even if the file grew in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if reading fails, or an error if the file shrank below
+    /// *length* since it was opened. Part of the body may have gone out already, the
+    /// connection can't be used for another response then.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = (buf.len() as u64).min(state.remaining) as usize;
+        let size = state.file.read(&mut buf[..wanted])?;
+        if size == 0 {
+            return Err(io::Error::other(format!(
+                "file shrank while being sent, {} of {} bytes missing",
+                state.remaining, self.length
+            )));
+        }
+        state.remaining -= size as u64;
+        Ok(size)
+    }
+    /// Copy the rest of the body to *out*, see *read*.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if reading or writing fails, or if the file shrank.
+    pub fn copy_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut buf = vec![0; FILE_CHUNK_SIZE];
+        loop {
+            match self.read(&mut buf)? {
+                0 => return Ok(()),
+                size => out.write_all(&buf[..size])?,
+            }
+        }
+    }
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            version: "HTTP/1.1",
+            status,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            chunks: None,
+            file: None,
+        }
+    }
+    /// Build a response whose body is sent with `Transfer-Encoding: chunked`, one chunk
+    /// per item, so it can start going out before all of it is known.
+    ///
+    /// Empty items are skipped, an empty chunk would end the body early.
+    pub fn streaming<I>(status: StatusCode, chunks: I) -> Response
+        where I: IntoIterator<Item = Vec<u8>>, I::IntoIter: Send + 'static, {
+            let chunks: Box<dyn Iterator<Item = Vec<u8>> + Send> = Box::new(chunks.into_iter());
+            let mut response = Response::new(status).header("Transfer-Encoding", "chunked");
+            response.chunks = Some(Chunks(Arc::new(Mutex::new(chunks))));
+            response
+        }
+    /// Build a response carrying the contents of a file, with its `Content-Type`
+    /// inferred from the file extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the file can't be read.
+    pub fn file<P: AsRef<Path>>(status: StatusCode, path: P) -> io::Result<Response> {
+        let path = path.as_ref();
+        let body = fs::read(path)?;
+        Ok(Response::new(status)
+            .header("Content-Type", content_type(path))
+            .body(body))
+    }
+    /// Like *file*, but the file is sent from disk while the response is written
+    /// instead of being read into memory first, for large downloads.
+    ///
+    /// `Content-Length` is the length of the file as of now, see *FileBody::read*.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the file can't be opened.
+    pub fn stream_file<P: AsRef<Path>>(status: StatusCode, path: P) -> io::Result<Response> {
+        let path = path.as_ref();
+        let length = fs::metadata(path)?.len();
+        let mut response = Response::new(status).header("Content-Type", content_type(path));
+        response.file = Some(FileBody::open(path, 0, length)?);
+        Ok(response)
+    }
+    /// Build a redirect to *location*, with a short HTML body linking there for clients
+    /// that don't follow the `Location` header.
+    ///
+    /// `MOVED_PERMANENTLY` and `PERMANENT_REDIRECT` can be cached by the client, `FOUND`,
+    /// `SEE_OTHER` and `TEMPORARY_REDIRECT` are for this one request. The 307 and 308
+    /// keep the method and the body of the request, the others may turn it into a GET.
+    /// Control characters in *location* are percent-encoded, so it can't break out of
+    /// the header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the status isn't a 3xx code, or is `NOT_MODIFIED` which redirects nowhere.
+    pub fn redirect(status: StatusCode, location: &str) -> Response {
+        assert!(
+            status.is_redirection() && status != StatusCode::NOT_MODIFIED,
+            "{} is not a redirect status",
+            status
+        );
+        let location: String = location
+            .chars()
+            .map(|c| if c.is_control() { percent_encode(&c.to_string()) } else { c.to_string() })
+            .collect();
+        let link = escape_html(&location);
+        Response::new(status)
+            .header("Location", &location)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(format!("<!DOCTYPE html>\n<html>\n<body>\n<p>Moved to <a href=\"{0}\">{0}</a>.</p>\n</body>\n</html>\n", link))
+    }
+    /// Build a response with the value serialized as its JSON body.
+    ///
+    /// A value that can't be serialized (.e.g. holding a NaN) is logged and answered
+    /// with a `500 Internal Server Error` instead.
+    pub fn json<T: ToJson + ?Sized>(status: StatusCode, value: &T) -> Response {
+        match value.to_json().serialize() {
+            Ok(body) => Response::new(status)
+                .header("Content-Type", "application/json")
+                .body(body),
+            Err(e) => {
+                log(Level::Error, &format!("failed to serialize a JSON response: {}", e));
+                Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error\n")
+            }
+        }
+    }
+    /// Add a header, after any others of the same name, see *HeaderMap::append*.
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+    /// Answer an HTTP/1.0 client, which knows neither chunked encoding nor keep-alive
+    /// by default.
+    ///
+    /// The status line says `HTTP/1.0`, and a streaming body is collected up front so
+    /// it can go out with a `Content-Length`.
+    pub fn for_http10(mut self) -> Response {
+        self.version = "HTTP/1.0";
+        if let Some(chunks) = self.chunks.take() {
+            self.body = chunks.0.lock().unwrap().by_ref().flatten().collect();
+            self.headers.remove("Transfer-Encoding");
+        }
+        self
+    }
+    /// The value of the first header with the given name, compared case-insensitively.
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+    /// The number of body bytes the response sends, not counting a streaming body
+    /// which isn't known up front.
+    pub fn body_length(&self) -> u64 {
+        self.file.as_ref().map_or(self.body.len() as u64, |file| file.length)
+    }
+    /// Answer a HEAD request with the headers the GET would get, including its
+    /// `Content-Length`, but without the body.
+    pub fn without_body(mut self) -> Response {
+        if self.chunks.take().is_none() {
+            let length = self.body_length();
+            self.file = None;
+            self.body.clear();
+            if !self.status.is_bodyless() && self.header_value("Content-Length").is_none() {
+                self = self.header("Content-Length", &length.to_string());
+            }
+        }
+        self
+    }
+    /// Gzip the body if the request accepts it, see *maybe_compress*.
+    pub fn compress(mut self, request_headers: &HeaderMap, threshold: usize) -> Response {
+        // the byte offsets of "Content-Range" refer to the body as it is, and a streaming
+        // body (or one from a file) isn't there yet to be compressed. an encoded one
+        // already is.
+        let encoded = self.header_value("Content-Encoding").is_some();
+        if encoded || self.header_value("Content-Range").is_some() || self.chunks.is_some() || self.file.is_some() {
+            return self;
+        }
+        let content_type = self.header_value("Content-Type").unwrap_or("").to_string();
+        let body = std::mem::take(&mut self.body);
+        let (body, encoding) = maybe_compress(body, request_headers, &content_type, threshold);
+        self.body = body;
+        if let Some(encoding) = encoding {
+            self = self.header("Content-Encoding", encoding);
+        }
+        if is_compressible(&content_type) {
+            // caches must keep the compressed and the plain body apart.
+            self = self.header("Vary", "Accept-Encoding");
+        }
+        self
+    }
+    /// Serialize the response, `Content-Length` is computed from the body unless it's
+    /// already set, see *without_body*. A `Date` header is added unless there's one.
+    ///
+    /// A `204 No Content` and a `304 Not Modified` are the exception, they have no body
+    /// (and the `Content-Length` of a 304 would have to describe the body it stands in
+    /// for), so it's left out. So is a streaming response, whose chunks are consumed here
+    /// like the file of a *FileBody* is read.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // writing to a "Vec" can't fail.
+        self.write_to(&mut bytes).unwrap();
+        bytes
+    }
+    /// Write the response, see *to_bytes*. Chunks of a streaming response are
+    /// written one at a time, as the iterator produces them, a file body a piece at a time.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if writing fails, or if the file of a *FileBody* shrank.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let head = self.head();
+        if let Some(file) = &self.file {
+            out.write_all(head.as_bytes())?;
+            return file.copy_to(out);
+        }
+        let chunks = match &self.chunks {
+            Some(chunks) => chunks,
+            None => {
+                let mut bytes = head.into_bytes();
+                bytes.extend_from_slice(&self.body);
+                // a single write, so small responses leave in a single packet.
+                return out.write_all(&bytes);
+            }
+        };
+        out.write_all(head.as_bytes())?;
+        for chunk in &mut *chunks.0.lock().unwrap() {
+            if chunk.is_empty() {
+                continue;
+            }
+            // the size in hex, the data, then CRLF.
+            out.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+            out.write_all(&chunk)?;
+            out.write_all(b"\r\n")?;
+        }
+        // the last chunk has a size of zero, and there are no trailers.
+        out.write_all(b"0\r\n\r\n")
+    }
+    /// The status line and the headers up to the blank line, as *write_to* sends them
+    /// ahead of the body.
+    pub fn head(&self) -> String {
+        let mut head = self.status.status_line(self.version);
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if self.header_value("Date").is_none() {
+            // as of sending, not of building the response.
+            head.push_str(&format!("Date: {}\r\n", UtcDateTime::now().to_http_date()));
+        }
+        // set by hand for a HEAD response, which describes a body it doesn't carry.
+        let framed = self.header_value("Content-Length").is_some() || self.header_value("Transfer-Encoding").is_some();
+        if self.chunks.is_none() && !self.status.is_bodyless() && !framed {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body_length()));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// What a `Range` header asks for out of a body of a given length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// No usable range, the whole body is sent.
+    Full,
+    /// The first and the last byte offset, both inclusive.
+    Partial(usize, usize),
+    /// The range starts past the end of the body, answered with a 416.
+    Unsatisfiable,
+}
+
+/// Resolve a single-range `Range` header, .e.g. `bytes=0-1023`, `bytes=500-` or
+/// `bytes=-500` (the last 500 bytes), against a body of `length` bytes.
+///
+/// Range sets (`bytes=0-1,5-6`), other units and malformed values all give
+/// `ByteRange::Full`, ignoring the header is always allowed by RFC 7233.
+pub fn parse_range(header: &str, length: usize) -> ByteRange {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+    match (first.parse::<usize>(), last.parse::<usize>()) {
+        // a suffix: the last *n* bytes, or all of them for a shorter body.
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 || length == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(length.saturating_sub(suffix), length - 1)
+            }
+        }
+        (Ok(start), _) if start >= length => ByteRange::Unsatisfiable,
+        // open-ended, up to the end of the body.
+        (Ok(start), Err(_)) if last.is_empty() => ByteRange::Partial(start, length - 1),
+        (Ok(start), Ok(end)) if start <= end => ByteRange::Partial(start, end.min(length - 1)),
+        _ => ByteRange::Full,
+    }
+}
+
+/// Gzip the body when the client sent `Accept-Encoding: gzip`.
+///
+/// Bodies of already-compressed media types (png, jpg, ...) and bodies smaller than
+/// the threshold are returned untouched. The returned encoding is meant for the
+/// `Content-Encoding` header, `None` meaning the body wasn't compressed.
+pub fn maybe_compress(
+    body: Vec<u8>,
+    headers: &HeaderMap,
+    content_type: &str,
+    threshold: usize,
+) -> (Vec<u8>, Option<&'static str>) {
+    if body.len() < threshold || !is_compressible(content_type) || !accepts_gzip(headers) {
+        return (body, None);
+    }
+    (gzip::compress(&body), Some("gzip"))
+}
+
+// text compresses well, images and archives are compressed already.
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    media_type.starts_with("text/")
+        || media_type == "application/javascript"
+        || media_type == "application/json"
+        || media_type == "image/svg+xml"
+}
+
+/// Whether the client takes a gzip-encoded body, .e.g. with
+/// `Accept-Encoding: deflate, gzip;q=1.0, *;q=0.5`. `q=0` rules an encoding out.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let accept_encoding = match headers.get_joined("accept-encoding") {
+        Some(accept_encoding) => accept_encoding,
+        None => return false,
+    };
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let rejected = params.any(|param| {
+            let param = param.trim();
+            param.starts_with("q=") && param[2..].trim().parse::<f32>() == Ok(0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip")) && !rejected
+    })
+}
+
+/// Split an `Accept` header into its media ranges, the preferred ones first: by quality,
+/// then the more specific ones, then in the order sent. .e.g. `text/html`, `text/*` and
+/// `*/*` for `*/*;q=0.1, text/*;q=0.5, text/html`.
+///
+/// An entry that isn't a `type/subtype` or has a `q` outside of 0 to 1 is left out. A
+/// lone `*`, which some clients send, is taken as `*/*`.
+pub fn parse_accept(value: &str) -> Vec<MediaRange> {
+    let mut ranges: Vec<MediaRange> = value
+        .split(',')
+        .filter_map(|entry| {
+            let media_type = entry.split(';').next()?.trim().to_ascii_lowercase();
+            let media_type = if media_type == "*" { "*/*".to_string() } else { media_type };
+            match media_type.split_once('/') {
+                Some(("*", subtype)) if subtype != "*" => return None,
+                Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() && !subtype.contains('/') => {}
+                _ => return None,
+            }
+            let quality = match multipart::parameter(entry, "q") {
+                Some(quality) => quality.parse::<f32>().ok().filter(|quality| (0.0..=1.0).contains(quality))?,
+                None => 1.0,
+            };
+            Some(MediaRange { media_type, quality })
+        })
+        .collect();
+    // stable, so equally preferred ranges keep their order.
+    ranges.sort_by(|a, b| {
+        b.quality
+            .partial_cmp(&a.quality)
+            .unwrap_or(Ordering::Equal)
+            .then(b.specificity().cmp(&a.specificity()))
+    });
+    ranges
+}
+
+/// Guess the media type of a file from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Json;
+
+    #[test]
+    fn the_path_is_percent_decoded() {
+        let request = Request::parse(b"GET /my%20file.html?q=a%20b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/my file.html");
+        assert_eq!(request.target, "/my%20file.html?q=a%20b");
+        assert_eq!(request.query["q"], "a b");
+        // a "+" is only a space in the query string.
+        assert_eq!(Request::parse(b"GET /a+b HTTP/1.1\r\n\r\n").unwrap().path, "/a+b");
+    }
+
+    #[test]
+    fn an_escaped_slash_in_the_path_is_refused() {
+        for path in ["/a%2Fb", "/a%2fb", "/a%00b"] {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            assert_eq!(
+                Request::parse(raw.as_bytes()),
+                Err(ParseError::InvalidPath(DecodeError::EscapedSeparator)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn a_malformed_escape_in_the_path_is_refused() {
+        let parse = |path: &str| Request::parse(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes());
+        assert_eq!(parse("/%zz"), Err(ParseError::InvalidPath(DecodeError::InvalidEscape)));
+        assert_eq!(parse("/a%2"), Err(ParseError::InvalidPath(DecodeError::InvalidEscape)));
+        assert_eq!(parse("/%ff"), Err(ParseError::InvalidPath(DecodeError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn a_path_climbing_above_the_root_is_refused() {
+        let parse = |path: &str| Request::parse(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes());
+        assert_eq!(parse("/../../etc/passwd"), Err(ParseError::InvalidPath(DecodeError::EscapesRoot)));
+        assert_eq!(parse("/a/%2e%2e/%2e%2e/etc"), Err(ParseError::InvalidPath(DecodeError::EscapesRoot)));
+        assert_eq!(parse("/a/b/../../etc").unwrap().path, "/etc");
+    }
+
+    #[test]
+    fn the_path_is_normalized() {
+        let path = |target: &str| Request::parse(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()).unwrap().path;
+        assert_eq!(path("/"), "/");
+        assert_eq!(path("//admin/./x"), "/admin/x");
+        assert_eq!(path("/a/../b/"), "/b/");
+        assert_eq!(path("/a/%2e%2e/b"), "/b");
+        assert_eq!(path("/docs//"), "/docs/");
+        assert_eq!(Request::parse(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap().path, "*");
+    }
+
+    #[test]
+    fn a_chunked_body_is_decoded() {
+        let data = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\nExpires: never\r\n\r\nnext";
+        assert_eq!(decode_chunked(data), ChunkedStatus::Complete(b"Wikipedia ".to_vec(), data.len() - 4));
+    }
+
+    #[test]
+    fn a_split_chunked_body_needs_more() {
+        let data = b"4\r\nWiki\r\n0\r\n\r\n";
+        for end in 0..data.len() {
+            assert_eq!(decode_chunked(&data[..end]), ChunkedStatus::NeedMore, "{} bytes", end);
+        }
+    }
+
+    #[test]
+    fn a_chunked_body_is_decoded_as_it_arrives() {
+        let data = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\nExpires: never\r\n\r\nnext";
+        for step in 1..8 {
+            let mut decoder = ChunkedDecoder::new();
+            let mut end = 0;
+            let status = loop {
+                end = (end + step).min(data.len());
+                match decoder.advance(&data[..end]) {
+                    ChunkedStatus::NeedMore if end < data.len() => {}
+                    status => break status,
+                }
+            };
+            assert_eq!(status, ChunkedStatus::Complete(b"Wikipedia ".to_vec(), data.len() - 4), "{} at a time", step);
+        }
+        // the chunks decoded so far are kept, not decoded again.
+        let mut decoder = ChunkedDecoder::new();
+        assert_eq!(decoder.advance(b"4\r\nWiki\r\n"), ChunkedStatus::NeedMore);
+        assert_eq!(decoder.advance(b"4\r\nWiki\r\n0\r\n\r\n"), ChunkedStatus::Complete(b"Wiki".to_vec(), 14));
+        decoder.reset();
+        assert_eq!(decoder.advance(b"1\r\nx\r\n0\r\n\r\n"), ChunkedStatus::Complete(b"x".to_vec(), 11));
+    }
+
+    #[test]
+    fn a_huge_chunk_size_is_invalid() {
+        assert_eq!(decode_chunked(b"ffffffffffffffed\r\nx"), ChunkedStatus::Invalid);
+        assert_eq!(decode_chunked(b"ffffffffffffffff\r\nx"), ChunkedStatus::Invalid);
+        assert_eq!(decode_chunked(b"fffffffffffffffffff\r\nx"), ChunkedStatus::Invalid);
+        assert_eq!(decode_chunked(b"+4\r\nWiki\r\n0\r\n\r\n"), ChunkedStatus::Invalid);
+    }
+
+    #[test]
+    fn header_names_are_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", "text/html");
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        headers.insert("content-TYPE", "text/plain");
+        assert_eq!(headers.get_all("Content-Type"), ["text/plain"]);
+        // the name as first given.
+        assert_eq!(headers.iter().collect::<Vec<_>>(), [("Content-Type", "text/plain")]);
+        assert_eq!(headers.remove("CONTENT-type"), Some("text/plain".to_string()));
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn repeated_request_headers_keep_one_value_per_line() {
+        let raw = "Accept: text/html\r\nCookie: a=1\r\nACCEPT: application/json;q=0.5\r\nCookie: b=2\r\n\r\n";
+        let request = Request::parse(format!("GET / HTTP/1.1\r\n{}", raw).as_bytes()).unwrap();
+        assert_eq!(request.headers.len(), 4);
+        assert_eq!(request.headers.get_all("accept"), ["text/html", "application/json;q=0.5"]);
+        assert_eq!(request.headers.get("accept"), Some("text/html"));
+        assert_eq!(request.headers.get_joined("accept").as_deref(), Some("text/html, application/json;q=0.5"));
+        assert_eq!(request.headers.get_joined("cookie").as_deref(), Some("a=1; b=2"));
+        assert_eq!(request.headers.get_joined("range"), None);
+        assert_eq!(request.cookie("b"), Some("2"));
+        assert_eq!(request.preferred(&["application/json", "text/html"]), Some("text/html"));
+    }
+
+    #[test]
+    fn every_set_cookie_is_written_in_order() {
+        let response = Response::new(StatusCode::OK)
+            .header("Set-Cookie", "a=1")
+            .header("Content-Type", "text/plain")
+            .header("Set-Cookie", "b=2");
+        assert_eq!(response.headers.get_all("set-cookie"), ["a=1", "b=2"]);
+        let head = response.head();
+        let lines: Vec<&str> = head.split("\r\n").collect();
+        assert_eq!(&lines[..4], ["HTTP/1.1 200 OK", "Set-Cookie: a=1", "Content-Type: text/plain", "Set-Cookie: b=2"]);
+    }
+
+    // the value of a header in the head of a serialized response.
+    fn header_line<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+        head.split("\r\n").find_map(|line| {
+            let (header, value) = line.split_once(": ")?;
+            if header.eq_ignore_ascii_case(name) {
+                Some(value)
+            } else {
+                None
+            }
+        })
+    }
+
+    #[test]
+    fn content_length_counts_the_bytes_of_the_body() {
+        let body = "héllo wörld";
+        let bytes = Response::new(StatusCode::OK).body(body).to_bytes();
+        let written = String::from_utf8(bytes).unwrap();
+        let (head, sent) = written.split_once("\r\n\r\n").unwrap();
+        assert_eq!(header_line(head, "Content-Length"), Some("13"));
+        assert_eq!(sent.len(), 13);
+        assert_eq!(sent, body);
+        // no body, but still framed.
+        let written = String::from_utf8(Response::new(StatusCode::OK).to_bytes()).unwrap();
+        assert_eq!(header_line(&written, "Content-Length"), Some("0"));
+        assert_eq!(content_type(Path::new("index.html")), "text/html; charset=utf-8");
+        assert_eq!(content_type(Path::new("logo.png")), "image/png");
+        assert_eq!(content_type(Path::new("README")), "application/octet-stream");
+    }
+
+    #[test]
+    fn query_parameters_are_split_and_decoded() {
+        let pairs = |query: &str| parse_query(query);
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(pairs("q=rust+book&page=2"), [pair("q", "rust book"), pair("page", "2")]);
+        // an empty value and a missing one are the same.
+        assert_eq!(pairs("x="), [pair("x", "")]);
+        assert_eq!(pairs("flag"), [pair("flag", "")]);
+        assert_eq!(pairs("a=1&&b=2&"), [pair("a", "1"), pair("b", "2")]);
+        assert_eq!(pairs(""), []);
+        // a broken escape is kept as it was sent.
+        assert_eq!(pairs("a=%zz&b=%4&c=100%"), [pair("a", "%zz"), pair("b", "%4"), pair("c", "100%")]);
+        assert_eq!(pairs("name=%C3%A9&bad=%ff"), [pair("name", "é"), pair("bad", "\u{fffd}")]);
+
+        let request = Request::parse(b"GET /search?tag=a&tag=b&empty=&flag HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.query["tag"], "a");
+        assert_eq!(request.query_all("tag"), ["a", "b"]);
+        assert_eq!(request.query["empty"], "");
+        assert_eq!(request.query["flag"], "");
+    }
+
+    #[test]
+    fn a_request_line_needs_a_method_a_target_and_a_version() {
+        let parse = |raw: &[u8]| Request::parse(raw).map(|request| request.method);
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n\r\n"), Ok("GET".to_string()));
+        assert_eq!(parse(b"GET /index.html\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse(b"GET\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse(b"\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse(b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse(b"GET / HTTP/1.1 extra\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse(b"GET / HTTP/2.0\r\n\r\n"), Err(ParseError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn ranges_resolve_against_the_length_of_the_body() {
+        use ByteRange::{Full, Partial, Unsatisfiable};
+        assert_eq!(parse_range("bytes=0-99", 1000), Partial(0, 99));
+        assert_eq!(parse_range("bytes=500-", 1000), Partial(500, 999));
+        assert_eq!(parse_range("bytes=-500", 1000), Partial(500, 999));
+        // more than there is.
+        assert_eq!(parse_range("bytes=-5000", 1000), Partial(0, 999));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Partial(900, 999));
+        assert_eq!(parse_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Unsatisfiable);
+        // ignored, the whole body is sent.
+        for header in ["bytes=0-1,5-6", "items=0-1", "bytes=5-1", "bytes=x-y", "bytes=-", "bytes=12"] {
+            assert_eq!(parse_range(header, 1000), Full, "{}", header);
+        }
+    }
+
+    #[test]
+    fn a_streaming_response_is_written_in_chunks() {
+        let parts = vec![b"first, ".to_vec(), Vec::new(), b"second, ".to_vec(), b"and the third".to_vec()];
+        let written = Response::streaming(StatusCode::OK, parts).to_bytes();
+        let head_end = written.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let (head, body) = written.split_at(head_end);
+        let head = String::from_utf8_lossy(head);
+        assert_eq!(header_line(&head, "Transfer-Encoding"), Some("chunked"));
+        assert_eq!(header_line(&head, "Content-Length"), None);
+        // the empty part is left out, it would end the body.
+        assert_eq!(body, b"7\r\nfirst, \r\n8\r\nsecond, \r\nd\r\nand the third\r\n0\r\n\r\n");
+        let payload = b"first, second, and the third".to_vec();
+        assert_eq!(decode_chunked(body), ChunkedStatus::Complete(payload, body.len()));
+    }
+
+    struct User {
+        id: u32,
+        name: String,
+        admin: bool,
+    }
+    impl ToJson for User {
+        fn to_json(&self) -> Json {
+            Json::object(vec![
+                ("id", self.id.to_json()),
+                ("name", self.name.to_json()),
+                ("admin", self.admin.to_json()),
+            ])
+        }
+    }
+
+    #[test]
+    fn a_struct_is_sent_as_json() {
+        let user = User { id: 42, name: "Ferris \"the crab\"".to_string(), admin: false };
+        let written = String::from_utf8(Response::json(StatusCode::OK, &user).to_bytes()).unwrap();
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert_eq!(body, r#"{"id":42,"name":"Ferris \"the crab\"","admin":false}"#);
+        assert_eq!(header_line(head, "Content-Type"), Some("application/json"));
+        assert_eq!(header_line(head, "Content-Length"), Some(body.len().to_string().as_str()));
+
+        // a NaN has no JSON representation.
+        let response = Response::json(StatusCode::OK, &vec![1.0, f64::NAN]);
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.header_value("Content-Type"), Some("text/plain; charset=utf-8"));
+    }
+
+    #[test]
+    fn status_lines_carry_the_reason_phrase() {
+        for (status, line) in [
+            (StatusCode::OK, "HTTP/1.1 200 OK\r\n"),
+            (StatusCode::NOT_FOUND, "HTTP/1.1 404 Not Found\r\n"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "HTTP/1.1 500 Internal Server Error\r\n"),
+            (StatusCode::MOVED_PERMANENTLY, "HTTP/1.1 301 Moved Permanently\r\n"),
+        ] {
+            assert_eq!(status.status_line("HTTP/1.1"), line);
+        }
+        // a code without a phrase keeps the space before the empty one.
+        let unknown = StatusCode::from_u16(599).unwrap();
+        assert_eq!(unknown.canonical_reason(), None);
+        assert_eq!(unknown.status_line("HTTP/1.0"), "HTTP/1.0 599 \r\n");
+        assert_eq!(StatusCode::from_u16(99), None);
+        assert_eq!(StatusCode::from_u16(600), None);
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404 Not Found");
+    }
+
+    #[test]
+    fn a_redirect_has_its_status_and_location() {
+        for status in [StatusCode::MOVED_PERMANENTLY, StatusCode::FOUND, StatusCode::PERMANENT_REDIRECT] {
+            let response = Response::redirect(status, "/new?a=1&b=2");
+            assert_eq!(response.status, status);
+            assert_eq!(response.header_value("Location"), Some("/new?a=1&b=2"));
+            let body = String::from_utf8(response.body).unwrap();
+            assert!(body.contains("<a href=\"/new?a=1&amp;b=2\">"), "{}", body);
+        }
+        // a header can't be smuggled in through the location.
+        let response = Response::redirect(StatusCode::FOUND, "/next\r\nSet-Cookie: evil=1");
+        assert_eq!(response.header_value("Location"), Some("/next%0D%0ASet-Cookie: evil=1"));
+        assert_eq!(response.headers.get("set-cookie"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "304 Not Modified is not a redirect status")]
+    fn a_redirect_needs_a_redirect_status() {
+        Response::redirect(StatusCode::NOT_MODIFIED, "/");
+    }
+
+    // the status after handing the parser every piece of *raw* in turn, the buffer
+    // growing by one piece at a time.
+    fn parse_in_pieces(raw: &[u8], splits: &[usize]) -> HeadStatus {
+        let mut parser = HeadParser::new();
+        let mut buffer = Vec::new();
+        let mut from = 0;
+        for split in splits.iter().copied().chain(Some(raw.len())) {
+            buffer.extend_from_slice(&raw[from..split]);
+            from = split;
+            let status = parser.advance(&buffer);
+            if split < raw.len() {
+                assert_eq!(status, HeadStatus::NeedMore, "after {} bytes", split);
+            } else {
+                return status;
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn a_head_split_at_awkward_places_still_parses() {
+        let raw = b"GET /a HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n";
+        let whole = match parse_in_pieces(raw, &[]) {
+            HeadStatus::Complete(request, body_start) => (request, body_start),
+            status => panic!("{:?}", status),
+        };
+        // in the middle of "Host", between a CR and its LF, and between the two CRLFs at the end.
+        let mid_name = 19;
+        let between_cr_and_lf = raw.iter().position(|&byte| byte == b'\r').unwrap() + 1;
+        let before_the_last_crlf = raw.len() - 2;
+        let before_the_last_lf = raw.len() - 1;
+        for splits in [
+            vec![mid_name],
+            vec![between_cr_and_lf],
+            vec![between_cr_and_lf, mid_name, before_the_last_crlf, before_the_last_lf],
+            (1..raw.len()).collect(),
+        ] {
+            match parse_in_pieces(raw, &splits) {
+                HeadStatus::Complete(request, body_start) => {
+                    assert_eq!((&request, body_start), (&whole.0, whole.1), "split at {:?}", splits);
+                    assert_eq!(request.headers.get("host"), Some("example.com"));
+                }
+                status => panic!("split at {:?}: {:?}", splits, status),
+            }
+        }
+        assert_eq!(&raw[..mid_name], b"GET /a HTTP/1.1\r\nHo");
+        assert_eq!(whole.1, raw.len());
+    }
+
+    #[test]
+    fn a_bad_request_line_is_refused_before_the_head_ends() {
+        let mut parser = HeadParser::new();
+        let mut buffer = b"BLAH\r\n".to_vec();
+        assert_eq!(parser.advance(&buffer), HeadStatus::Error(ParseError::Malformed));
+        buffer.extend_from_slice(b"Host: example.com\r\n\r\n");
+        assert_eq!(parser.advance(&buffer), HeadStatus::Error(ParseError::Malformed));
+        // the body that came with the head stays in the buffer.
+        parser.reset();
+        match parser.advance(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi") {
+            HeadStatus::Complete(_, body_start) => assert_eq!(body_start, 38),
+            status => panic!("{:?}", status),
+        }
+    }
+
+    fn with_content_type(value: &str) -> Option<ContentType> {
+        Request::parse(format!("POST / HTTP/1.1\r\nContent-Type: {}\r\n\r\n", value).as_bytes()).unwrap().content_type()
+    }
+
+    #[test]
+    fn the_content_type_is_split_into_its_parts() {
+        let bare = with_content_type("Application/JSON").unwrap();
+        assert!(bare.is("application/json"));
+        assert_eq!((bare.media_type.as_str(), bare.charset, bare.boundary), ("application/json", None, None));
+
+        let text = with_content_type("text/plain ;  Charset = \"UTF-8\" ").unwrap();
+        assert_eq!(text.media_type, "text/plain");
+        assert_eq!(text.charset.as_deref(), Some("utf-8"));
+
+        let form = with_content_type("multipart/form-data; boundary=----WebKitFormBoundary7MA4YWxk").unwrap();
+        assert!(form.is("Multipart/Form-Data"));
+        assert_eq!(form.boundary.as_deref(), Some("----WebKitFormBoundary7MA4YWxk"));
+        // only a multipart type has one.
+        assert_eq!(with_content_type("text/plain; boundary=xyz").unwrap().boundary, None);
+
+        for invalid in ["json", "text/", "/plain", "a/b/c"] {
+            assert_eq!(with_content_type(invalid), None, "{}", invalid);
+        }
+        assert_eq!(Request::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap().content_type(), None);
+    }
+
+    fn accepting(accept: &str) -> Request {
+        Request::parse(format!("GET / HTTP/1.1\r\nAccept: {}\r\n\r\n", accept).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn accept_ranges_are_sorted_by_quality() {
+        let accept = "*/*;q=0.1, text/*;q=0.5, application/json;q=0.9, text/html, image/png;q=2, nonsense";
+        let ranges = parse_accept(accept);
+        let media_types: Vec<&str> = ranges.iter().map(|range| range.media_type.as_str()).collect();
+        assert_eq!(media_types, ["text/html", "application/json", "text/*", "*/*"]);
+        assert_eq!(ranges[1].quality, 0.9);
+
+        let offered = ["application/json", "text/html"];
+        assert_eq!(accepting("application/json;q=0.4, text/html;q=0.8").preferred(&offered), Some("text/html"));
+        assert_eq!(accepting("text/html;q=0.4, application/json").preferred(&offered), Some("application/json"));
+        // the more specific range decides, even when it's less preferred.
+        let request = accepting("text/*, text/html;q=0.2, application/*;q=0.5");
+        assert_eq!(request.preferred(&offered), Some("application/json"));
+    }
+
+    #[test]
+    fn a_wildcard_accepts_what_is_offered_first() {
+        let offered = ["application/json", "text/html"];
+        assert_eq!(accepting("*/*").preferred(&offered), Some("application/json"));
+        assert_eq!(accepting("*").preferred(&offered), Some("application/json"));
+        assert_eq!(accepting("text/plain, */*;q=0.1").preferred(&offered), Some("application/json"));
+        assert_eq!(accepting("text/*").preferred(&offered), Some("text/html"));
+        assert_eq!(Request::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap().preferred(&offered), Some("application/json"));
+    }
+
+    #[test]
+    fn nothing_acceptable_is_none() {
+        let offered = ["application/json", "text/html"];
+        assert_eq!(accepting("image/png").preferred(&offered), None);
+        assert_eq!(accepting("text/html;q=0, application/*;q=0").preferred(&offered), None);
+        assert_eq!(accepting("*/*, text/html;q=0").preferred(&["text/html"]), None);
+        assert_eq!(accepting("text/html").preferred(&[]), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// A JSON value, built directly or through *ToJson*.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // wide enough for every integer type, so none of them loses precision.
+    Int(i128),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    // kept in insertion order, that's the order the keys are written in.
+    Object(Vec<(String, Json)>),
+}
+
+/// Why a value couldn't be serialized.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    // NaN and the infinities have no JSON representation.
+    NonFiniteNumber(f64),
+}
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::NonFiniteNumber(n) => write!(f, "{} can't be represented in JSON", n),
+        }
+    }
+}
+impl Error for JsonError {}
+
+/// Types that can be turned into a `Json` value, .e.g. for *Response::json*.
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl Json {
+    /// Build an object from key-value pairs, .e.g.
+    /// `Json::object(vec![("id", 42.to_json()), ("name", "ferris".to_json())])`.
+    pub fn object<K: Into<String>>(pairs: Vec<(K, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(key, value)| (key.into(), value)).collect())
+    }
+    /// Serialize the value compactly, without any whitespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns `JsonError::NonFiniteNumber` if the value contains a NaN or an infinity.
+    pub fn serialize(&self) -> Result<String, JsonError> {
+        let mut out = String::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+    fn write(&self, out: &mut String) -> Result<(), JsonError> {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Int(n) => out.push_str(&n.to_string()),
+            Json::Float(n) if !n.is_finite() => return Err(JsonError::NonFiniteNumber(*n)),
+            Json::Float(n) => out.push_str(&n.to_string()),
+            Json::String(s) => write_string(s, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out)?;
+                }
+                out.push(']');
+            }
+            Json::Object(pairs) => {
+                out.push('{');
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write(out)?;
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+// quoted, with the characters JSON doesn't allow raw escaped.
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl ToJson for Json {
+    fn to_json(&self) -> Json {
+        self.clone()
+    }
+}
+impl ToJson for bool {
+    fn to_json(&self) -> Json {
+        Json::Bool(*self)
+    }
+}
+macro_rules! int_to_json {
+    ($($t:ty),*) => {
+        $(impl ToJson for $t {
+            fn to_json(&self) -> Json {
+                Json::Int(*self as i128)
+            }
+        })*
+    };
+}
+int_to_json!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl ToJson for f32 {
+    fn to_json(&self) -> Json {
+        Json::Float(f64::from(*self))
+    }
+}
+impl ToJson for f64 {
+    fn to_json(&self) -> Json {
+        Json::Float(*self)
+    }
+}
+impl ToJson for str {
+    fn to_json(&self) -> Json {
+        Json::String(self.to_string())
+    }
+}
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+}
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+impl<T: ToJson> ToJson for BTreeMap<String, T> {
+    fn to_json(&self) -> Json {
+        Json::Object(self.iter().map(|(key, value)| (key.clone(), value.to_json())).collect())
+    }
+}
+impl<T: ToJson> ToJson for HashMap<String, T> {
+    // sorted, so the same map always serializes the same way.
+    fn to_json(&self) -> Json {
+        let mut pairs: Vec<_> = self.iter().map(|(key, value)| (key.clone(), value.to_json())).collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        Json::Object(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_serialized_compactly_and_escaped() {
+        let value = Json::object(vec![
+            ("text", "tab\tquote\"back\\slash\u{1}".to_json()),
+            ("list", vec![Some(1u8), None].to_json()),
+            ("float", 1.5.to_json()),
+            ("big", u64::MAX.to_json()),
+        ]);
+        let expected = concat!(
+            r#"{"text":"tab\tquote\"back\\slash\u0001","#,
+            r#""list":[1,null],"float":1.5,"big":18446744073709551615}"#,
+        );
+        assert_eq!(value.serialize().unwrap(), expected);
+        let map: HashMap<String, bool> = vec![("b".to_string(), true), ("a".to_string(), false)].into_iter().collect();
+        assert_eq!(map.to_json().serialize().unwrap(), r#"{"a":false,"b":true}"#);
+        assert_eq!(f64::INFINITY.to_json().serialize(), Err(JsonError::NonFiniteNumber(f64::INFINITY)));
+    }
+}
@@ -0,0 +1,1823 @@
+pub mod access_log;
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod cors;
+pub mod date;
+pub mod gzip;
+pub mod histogram;
+pub mod http;
+pub mod json;
+pub mod listener;
+pub mod log;
+pub mod multipart;
+pub mod rate_limit;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod signal;
+pub mod websocket;
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::log::{log, Level};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+thread_local! {
+    // the id of the pool a worker thread belongs to, 0 on any other thread.
+    static CURRENT_POOL: Cell<usize> = const { Cell::new(0) };
+    // what *ThreadPoolBuilder::worker_state* made for this worker, see *execute_with_state*.
+    static WORKER_STATE: RefCell<Option<Box<dyn Any>>> = const { RefCell::new(None) };
+    // set by a job of *submit_cancellable* skipping itself, so it isn't counted as completed.
+    static SKIPPED: Cell<bool> = const { Cell::new(false) };
+}
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
+/// How the jobs submitted to a pool are handed out to its workers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Every worker takes its jobs from a single channel guarded by a mutex.
+    SharedQueue,
+    /// Every worker owns a deque, and steals from the others once its own runs dry.
+    WorkStealing,
+}
+
+/// Which of the queued jobs a free worker picks up next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheduling {
+    /// The oldest one, jobs run in the order they were submitted.
+    Fifo,
+    /// The newest one, so under load fresh requests are served first and the old ones
+    /// wait (and likely time out) instead of holding everybody up.
+    Lifo,
+}
+
+/// A snapshot of what a pool is doing, see *ThreadPool::stats*.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    /// Jobs submitted but not yet picked up by a worker.
+    pub queued: usize,
+    /// Jobs currently running on a worker.
+    pub active: usize,
+    /// Jobs that ran to completion.
+    pub completed: u64,
+    /// Jobs that panicked, the worker that ran them keeps going.
+    pub panicked: u64,
+}
+
+// returned by *ThreadPool::build* instead of panicking.
+#[derive(Debug)]
+pub enum PoolCreationError {
+    ZeroSize,
+    // the OS refused to create a worker thread.
+    Spawn(io::Error),
+}
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "thread pool size must be greater than zero"),
+            PoolCreationError::Spawn(e) => write!(f, "failed to spawn a worker thread: {}", e),
+        }
+    }
+}
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize => None,
+            PoolCreationError::Spawn(e) => Some(e),
+        }
+    }
+}
+
+/// Why *JobHandle::join* couldn't return the result of a job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobError {
+    /// The job panicked, the worker that ran it keeps going.
+    Panicked,
+    /// The job was dropped without running.
+    Lost,
+    /// The job's *CancelToken* was cancelled (or its deadline passed) before a worker
+    /// got to it, so it never ran.
+    Cancelled,
+}
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked => write!(f, "job panicked"),
+            JobError::Lost => write!(f, "job was dropped before it could run"),
+            JobError::Cancelled => write!(f, "job was cancelled before it could run"),
+        }
+    }
+}
+impl Error for JobError {}
+
+/// Why *ThreadPool::execute* turned a job away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecuteError {
+    /// The pool is shutting down (or already shut down).
+    ShuttingDown,
+    /// The bounded queue has no room left, only returned by *try_execute*.
+    Full,
+}
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::ShuttingDown => write!(f, "thread pool is shutting down"),
+            ExecuteError::Full => write!(f, "thread pool queue is full"),
+        }
+    }
+}
+impl Error for ExecuteError {}
+
+/// The receiving end of a job submitted with *ThreadPool::submit*.
+pub struct JobHandle<T> {
+    // a oneshot channel, the job sends exactly one message.
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+}
+impl<T> JobHandle<T> {
+    /// Block until the job has run and return what it returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `JobError::Panicked` if the job panicked, or `JobError::Lost` if it
+    /// was dropped without running.
+    pub fn join(self) -> Result<T, JobError> {
+        self.receiver.recv().unwrap_or(Err(JobError::Lost))
+    }
+}
+
+/// Asks a job submitted with *ThreadPool::submit_cancellable* to stop, cooperatively.
+///
+/// A job still queued when it's cancelled never runs. A running one isn't interrupted,
+/// it has to check *is_cancelled* every now and then and return early on its own.
+/// Clones share the same flag.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<CancelState>);
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    // the token counts as cancelled from then on.
+    deadline: Mutex<Option<Instant>>,
+}
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    /// A job still queued stays in the queue, and in *PoolStats::queued*, until a worker
+    /// gets to it. It's skipped then, without counting as *completed*. One already
+    /// running (or done) counts as completed as before, whether it gave up or not.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+    }
+    /// Cancel the token once *deadline* passes, .e.g. when a client stops waiting for
+    /// the result. Replaces an earlier deadline.
+    pub fn cancel_at(&self, deadline: Instant) {
+        *lock(&self.0.deadline) = Some(deadline);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+            || lock(&self.0.deadline).is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+pub struct ThreadPool {
+    // behind a mutex so the pool can be resized while other threads call *execute*.
+    workers: Mutex<Workers>,
+    queue: Queue,
+    // cloned into every worker the pool spawns.
+    context: WorkerContext,
+    metrics: PoolMetrics,
+    // set by *shutdown*, from then on *execute* turns jobs away.
+    shutting_down: AtomicBool,
+}
+
+struct Workers {
+    list: Vec<Worker>,
+    // terminated workers report their id here, see *WorkerContext::exited*.
+    exited: mpsc::Receiver<usize>,
+    // workers that exited on their own after idling, see *WorkerContext::retired*.
+    retired: mpsc::Receiver<usize>,
+    // recycled workers along with their replacements, see *WorkerContext::recycled*.
+    recycled: mpsc::Receiver<(usize, Worker)>,
+}
+impl Workers {
+    // join the workers that retired or were recycled since the last look, their threads
+    // are done. the replacements of recycled ones take their place in the list.
+    fn reap(&mut self) {
+        while let Ok(id) = self.retired.try_recv() {
+            log(Level::Info, &format!("Worker {} retired after idling.", id));
+            self.join(id);
+        }
+        while let Ok((id, replacement)) = self.recycled.try_recv() {
+            self.join(id);
+            self.list.push(replacement);
+        }
+    }
+    fn join(&mut self, id: usize) {
+        if let Some(index) = self.list.iter().position(|worker| worker.id == id) {
+            let mut worker = self.list.remove(index);
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+impl ThreadPool {
+    // the number of elements in a collection of threads.
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::build(size).unwrap()
+    }
+    /// Create a new ThreadPool, returning an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolCreationError::ZeroSize` if the size is zero, or
+    /// `PoolCreationError::Spawn` if a worker thread can't be created.
+    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::with_strategy(size, Strategy::SharedQueue)
+    }
+    /// Create a new ThreadPool handing out jobs according to the given strategy.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolCreationError::ZeroSize` if the size is zero, or
+    /// `PoolCreationError::Spawn` if a worker thread can't be created.
+    pub fn with_strategy(size: usize, strategy: Strategy) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPoolBuilder::new().size(size).strategy(strategy).build()
+    }
+    /// Start configuring a pool, see *ThreadPoolBuilder*.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<ThreadPool, PoolCreationError> {
+        let ThreadPoolBuilder { size, strategy, scheduling, queue_capacity, settings } = builder;
+        if size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+        let (queue, source) = match strategy {
+            // a channel can only ever hand out its oldest message.
+            Strategy::SharedQueue if scheduling == Scheduling::Lifo => {
+                let stack = Arc::new(Stack::new(queue_capacity));
+                (Queue::Stack(Arc::clone(&stack)), Source::Stack(stack))
+            }
+            Strategy::SharedQueue => {
+                let (sender, receiver) = match queue_capacity {
+                    Some(capacity) => {
+                        let (sender, receiver) = mpsc::sync_channel(capacity);
+                        (Sender::Bounded(sender), receiver)
+                    }
+                    None => {
+                        let (sender, receiver) = mpsc::channel();
+                        (Sender::Unbounded(sender), receiver)
+                    }
+                };
+                let receiver = Arc::new(Mutex::new(receiver));
+                (Queue::Shared(sender), Source::Shared(receiver))
+            }
+            Strategy::WorkStealing => {
+                let deques = Arc::new(Deques::new(queue_capacity, scheduling));
+                (Queue::Stealing(Arc::clone(&deques)), Source::Stealing(deques))
+            }
+        };
+        let metrics = PoolMetrics::default();
+        let (exited_sender, exited) = mpsc::channel();
+        let (retired_sender, retired) = mpsc::channel();
+        let (recycled_sender, recycled) = mpsc::channel();
+        let context = WorkerContext {
+            source,
+            metrics: metrics.clone(),
+            exited: exited_sender,
+            retired: retired_sender,
+            recycled: recycled_sender,
+            next_id: Arc::new(Mutex::new(0)),
+            live: Arc::new(AtomicUsize::new(0)),
+            settings: Arc::new(settings),
+        };
+
+        let workers = Mutex::new(Workers {
+            // preallocates space in the vector (more effecient than *Vec::new*).
+            list: Vec::with_capacity(size),
+            exited,
+            retired,
+            recycled,
+        });
+        let pool = ThreadPool {
+            workers,
+            queue,
+            context,
+            metrics,
+            shutting_down: AtomicBool::new(false),
+        };
+        // if spawning fails half way, dropping the pool stops the workers spawned so far.
+        pool.set_size(size)?;
+        Ok(pool)
+    }
+    /// Queue a job to run on one of the workers.
+    ///
+    /// With a bounded queue (see *ThreadPoolBuilder::queue_capacity*) this blocks
+    /// until there's room for the job.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExecuteError::ShuttingDown` once *shutdown* has started, the job is
+    /// dropped without running.
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+        // the lifetime would be the same as the whole app.
+        where F: FnOnce() + Send + 'static, {
+            self.enqueue(Box::new(f), true)
+        }
+    /// Like *execute*, but the job gets the state of the worker it runs on, the one
+    /// *ThreadPoolBuilder::worker_state* created when that worker started.
+    ///
+    /// Jobs on the same worker see each other's changes to it, jobs on other workers
+    /// never do.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExecuteError::ShuttingDown` once *shutdown* has started, see *execute*.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool wasn't built with a worker state of type `S`.
+    pub fn execute_with_state<S, F>(&self, f: F) -> Result<(), ExecuteError>
+        where S: 'static, F: FnOnce(&mut S) + Send + 'static, {
+            let state_type = self.context.settings.state_init.as_ref().map(|(type_id, _)| *type_id);
+            assert!(
+                state_type == Some(TypeId::of::<S>()),
+                "the pool has no worker state of type {}",
+                std::any::type_name::<S>()
+            );
+            self.execute(move || {
+                WORKER_STATE.with(|state| {
+                    let mut state = state.borrow_mut();
+                    // only missing if creating it panicked when the worker started.
+                    let state = state
+                        .as_mut()
+                        .and_then(|state| state.downcast_mut::<S>())
+                        .expect("the worker has no state, creating it failed");
+                    f(state)
+                })
+            })
+        }
+    /// Call *handler* with the payload whenever a job panics from now on, replacing the
+    /// one given to *ThreadPoolBuilder::panic_handler*, .e.g. to count panics in an
+    /// external metric or to send an alert.
+    ///
+    /// It runs on the worker that ran the job, after the worker logged the panic and
+    /// recovered from it. A panicking handler is caught as well, the worker keeps going.
+    pub fn on_panic<F>(&self, handler: F)
+        where F: Fn(&(dyn Any + Send)) + Send + Sync + 'static, {
+            let mut panic_handler = self.context.settings.panic_handler.write().unwrap_or_else(PoisonError::into_inner);
+            *panic_handler = Some(Arc::new(handler));
+        }
+    /// Like *execute*, but never blocks on a full queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ExecuteError::Full` if the bounded queue has no room left, and
+    /// `ExecuteError::ShuttingDown` once *shutdown* has started. The job is dropped
+    /// without running in both cases.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError>
+        where F: FnOnce() + Send + 'static, {
+            self.enqueue(Box::new(f), false)
+        }
+    fn enqueue(&self, job: Job, block: bool) -> Result<(), ExecuteError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(ExecuteError::ShuttingDown);
+        }
+        // counted before it can be picked up, so *queued* never goes below zero.
+        self.metrics.0.queued.fetch_add(1, Ordering::SeqCst);
+        let queued = match &self.queue {
+            Queue::Shared(sender) if block => sender.send(Message::NewJob(job)).map_err(|_| ExecuteError::ShuttingDown),
+            Queue::Shared(sender) => sender.try_send(Message::NewJob(job)).map_err(|e| match e {
+                TrySendError::Full(_) => ExecuteError::Full,
+                TrySendError::Disconnected(_) => ExecuteError::ShuttingDown,
+            }),
+            Queue::Stealing(deques) if block => {
+                deques.push(job);
+                Ok(())
+            }
+            Queue::Stealing(deques) => deques.try_push(job).map_err(|_| ExecuteError::Full),
+            Queue::Stack(stack) if block => {
+                stack.push(job);
+                Ok(())
+            }
+            Queue::Stack(stack) => stack.try_push(job).map_err(|_| ExecuteError::Full),
+        };
+        if queued.is_err() {
+            self.metrics.0.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        queued
+    }
+    /// Like *execute*, but hand the value returned by the job back through a handle.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+        where F: FnOnce() -> T + Send + 'static, T: Send + 'static, {
+            let (sender, receiver) = mpsc::channel();
+            // a rejected job drops the sender, so the handle reports it as lost.
+            let _ = self.execute(move || deliver(sender, f));
+            JobHandle { receiver }
+        }
+    /// Like *submit*, but along with a token to cancel the job with, see *CancelToken*.
+    ///
+    /// The job gets the token as well, to check whether it should give up. One
+    /// cancelled while still queued is skipped, its handle returns `JobError::Cancelled`
+    /// and it doesn't count as completed in *stats*.
+    pub fn submit_cancellable<F, T>(&self, f: F) -> (JobHandle<T>, CancelToken)
+        where F: FnOnce(&CancelToken) -> T + Send + 'static, T: Send + 'static, {
+            let token = CancelToken::new();
+            let job_token = token.clone();
+            let (sender, receiver) = mpsc::channel();
+            let _ = self.execute(move || {
+                if job_token.is_cancelled() {
+                    SKIPPED.with(|skipped| skipped.set(true));
+                    let _ = sender.send(Err(JobError::Cancelled));
+                    return;
+                }
+                deliver(sender, || f(&job_token))
+            });
+            (JobHandle { receiver }, token)
+        }
+    /// Run *f* on every item, spread over the workers in one chunk per worker, and
+    /// return the results in the order of the items.
+    ///
+    /// Blocks until all chunks are done. Called from a job running on this same pool
+    /// the items are mapped right there on the calling worker instead, waiting for
+    /// other workers could deadlock once all of them wait like that. Nested calls
+    /// therefore don't add any parallelism, only the outermost one does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if *f* panics on any item, or if the pool is shutting down.
+    pub fn fork_join<T, R, F>(&self, items: Vec<T>, f: F) -> Vec<R>
+        where T: Send + 'static, R: Send + 'static, F: Fn(T) -> R + Send + Sync + 'static, {
+            if CURRENT_POOL.with(|pool| pool.get()) == self.metrics.id() {
+                return items.into_iter().map(f).collect();
+            }
+            let f = Arc::new(f);
+            let chunk_size = items.len().div_ceil(self.size().max(1)).max(1);
+            let mut items = items.into_iter();
+            let mut handles = Vec::new();
+            loop {
+                let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                let f = Arc::clone(&f);
+                handles.push(self.submit(move || chunk.into_iter().map(|item| f(item)).collect::<Vec<R>>()));
+            }
+            // the handles are in chunk order, so the results come out in item order.
+            handles
+                .into_iter()
+                .flat_map(|handle| match handle.join() {
+                    Ok(results) => results,
+                    Err(e) => panic!("fork_join: {}", e),
+                })
+                .collect()
+        }
+    /// The number of workers in the pool, not counting the ones asked to terminate
+    /// or retiring after idling (see *ThreadPoolBuilder::idle_timeout*).
+    pub fn size(&self) -> usize {
+        lock(&self.workers).reap();
+        self.context.live.load(Ordering::SeqCst)
+    }
+    /// Grow or shrink the pool to the given number of workers.
+    ///
+    /// Growing spawns the missing workers right away, which is also how a pool that
+    /// shrank while idle scales back up. Shrinking asks exactly `old - new` workers to
+    /// terminate and blocks until those are joined, a worker only sees the request
+    /// once it's done with the jobs queued before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolCreationError::ZeroSize` if the new size is zero, or
+    /// `PoolCreationError::Spawn` if a new worker thread can't be created.
+    pub fn set_size(&self, new_size: usize) -> Result<(), PoolCreationError> {
+        if new_size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+        // held for the whole resize, so two of them can't interleave.
+        let mut workers = lock(&self.workers);
+        workers.reap();
+        let live = &self.context.live;
+        // idle workers may retire concurrently, but only ever down to *min_size*.
+        let old_size = live.load(Ordering::SeqCst);
+        if new_size > old_size {
+            for _ in old_size..new_size {
+                let id = self.context.next_id();
+                let queue = self.context.claim_queue();
+                live.fetch_add(1, Ordering::SeqCst);
+                let worker = Worker::new(id, queue, self.context.clone()).map_err(|e| {
+                    live.fetch_sub(1, Ordering::SeqCst);
+                    self.context.release_queue(queue);
+                    PoolCreationError::Spawn(e)
+                })?;
+                workers.list.push(worker);
+            }
+        } else {
+            // taken off *live* before the terminates go out, so there's always a worker
+            // left to pick each of them up.
+            let old_size = live
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| Some(live.min(new_size)))
+                .unwrap();
+            let count = old_size.saturating_sub(new_size);
+            self.terminate(count);
+            // whichever workers picked up the terminates are the ones to join.
+            for _ in 0..count {
+                let id = workers.exited.recv().unwrap();
+                // it may be the replacement of a worker recycled in the meantime.
+                workers.reap();
+                workers.join(id);
+            }
+        }
+        Ok(())
+    }
+    // ask that many workers (whichever get to it first) to exit.
+    fn terminate(&self, count: usize) {
+        match &self.queue {
+            Queue::Shared(sender) => {
+                for _ in 0..count {
+                    sender.send(Message::Terminate).unwrap();
+                }
+            }
+            Queue::Stealing(deques) => deques.terminate(count),
+            Queue::Stack(stack) => stack.terminate(count),
+        }
+    }
+    /// The current queued, active and completed job counts.
+    pub fn stats(&self) -> PoolStats {
+        self.metrics.stats()
+    }
+    /// Block until every job submitted so far has finished running.
+    ///
+    /// Unlike *shutdown* the workers stay up, so more jobs can be submitted afterwards.
+    /// Jobs submitted by other threads while waiting are waited for as well.
+    pub fn join(&self) {
+        let counters = &self.metrics.0;
+        let mut idle = lock(&counters.idle);
+        while counters.queued.load(Ordering::SeqCst) > 0 || counters.active.load(Ordering::SeqCst) > 0 {
+            idle = wait(&counters.drained, idle);
+        }
+    }
+    /// A handle reading the same counters as *stats*, which can be moved into jobs.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.metrics.clone()
+    }
+    /// Stop all workers once they are done with the jobs already submitted.
+    ///
+    /// Blocks until every worker has been joined, calling it again is a no-op. Jobs
+    /// submitted afterwards are rejected, see *execute*.
+    pub fn shutdown(&mut self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        // retired workers are still in the list, they're simply joined below.
+        let count = self.context.live.swap(0, Ordering::SeqCst);
+        if self.workers.get_mut().unwrap_or_else(PoisonError::into_inner).list.is_empty() {
+            return;
+        }
+        log(Level::Info, "Sending terminate message to all workers.");
+        self.terminate(count);
+        log(Level::Info, "Shutting down all workers.");
+        let workers = self.workers.get_mut().unwrap_or_else(PoisonError::into_inner);
+        // drain the workers, so a later call (e.g. from *Drop*) has nothing left to stop.
+        // a worker recycled while finishing the last jobs hands the terminate on to its
+        // replacement, which is only in the list after the next *reap*.
+        while !workers.list.is_empty() {
+            for mut worker in workers.list.drain(..) {
+                log(Level::Info, &format!("Shutting down worker {}", worker.id));
+                if let Some(thread) = worker.thread.take() {
+                    thread.join().unwrap();
+                }
+            }
+            workers.reap();
+        }
+    }
+}
+impl ThreadPool {
+    /// Like *shutdown*, but give up on workers that haven't finished their current job
+    /// by the time the timeout expires.
+    ///
+    /// Returns the ids of those workers, an empty list meaning all of them were joined.
+    /// Their threads are detached rather than joined, they still exit once done with
+    /// their job, so the caller decides whether to wait longer or exit the process.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> Vec<usize> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        let count = self.context.live.swap(0, Ordering::SeqCst);
+        // a worker that retired already is done for sure.
+        self.workers.get_mut().unwrap_or_else(PoisonError::into_inner).reap();
+        if self.workers.get_mut().unwrap_or_else(PoisonError::into_inner).list.is_empty() {
+            return Vec::new();
+        }
+        log(Level::Info, "Sending terminate message to all workers.");
+        self.terminate(count);
+        let workers = self.workers.get_mut().unwrap_or_else(PoisonError::into_inner);
+        // every worker reports its id right before its thread ends, see *Worker::new*.
+        for _ in 0..count {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let id = match workers.exited.recv_timeout(left) {
+                Ok(id) => id,
+                Err(_) => break,
+            };
+            log(Level::Info, &format!("Shutting down worker {}", id));
+            workers.reap();
+            workers.join(id);
+        }
+        workers.reap();
+        // dropping the handles detaches the threads, so *Drop* doesn't wait for them either.
+        let late: Vec<usize> = workers.list.drain(..).map(|worker| worker.id).collect();
+        if !late.is_empty() {
+            log(Level::Warn, &format!("Workers {:?} didn't finish in time.", late));
+        }
+        late
+    }
+}
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// run a submitted job and send what it returned to its *JobHandle*.
+fn deliver<F, T>(sender: mpsc::Sender<Result<T, JobError>>, f: F)
+    where F: FnOnce() -> T, {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            // the handle may have been dropped already, nobody wants the result then.
+            Ok(value) => {
+                let _ = sender.send(Ok(value));
+            }
+            Err(payload) => {
+                let _ = sender.send(Err(JobError::Panicked));
+                // let the worker see the panic too, so it's counted and logged as usual.
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+
+type PanicHandler = dyn Fn(&(dyn Any + Send)) + Send + Sync;
+type StateInit = dyn Fn() -> Box<dyn Any> + Send + Sync;
+
+/// Configures a pool before creating it, for the knobs *ThreadPool::new* doesn't take,
+/// .e.g. `ThreadPool::builder().size(8).thread_name_prefix("http").build()`.
+pub struct ThreadPoolBuilder {
+    size: usize,
+    strategy: Strategy,
+    scheduling: Scheduling,
+    // *None* for an unbounded queue.
+    queue_capacity: Option<usize>,
+    settings: WorkerSettings,
+}
+
+// what every worker of a pool is spawned with.
+struct WorkerSettings {
+    // threads are named "{prefix}-{id}".
+    name_prefix: String,
+    // *None* keeps the platform's default.
+    stack_size: Option<usize>,
+    // called with the payload of a panicking job, on the worker that ran it. replaced by
+    // *ThreadPool::on_panic* while the workers are running.
+    panic_handler: RwLock<Option<Arc<PanicHandler>>>,
+    // a worker that waited this long without getting a job exits, *None* keeps it forever.
+    idle_timeout: Option<Duration>,
+    // idle workers only exit while more than this many are left.
+    min_size: usize,
+    // a worker is replaced by a fresh thread after running this many jobs, 0 for never.
+    max_jobs: usize,
+    // creates the state of each worker as it starts, tagged with the type it returns.
+    state_init: Option<(TypeId, Box<StateInit>)>,
+}
+impl ThreadPoolBuilder {
+    /// One worker per available CPU, a shared FIFO queue and threads named `worker-{id}`.
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size: thread::available_parallelism().map_or(1, |count| count.get()),
+            strategy: Strategy::SharedQueue,
+            scheduling: Scheduling::Fifo,
+            queue_capacity: None,
+            settings: WorkerSettings {
+                name_prefix: "worker".to_string(),
+                stack_size: None,
+                panic_handler: RwLock::new(None),
+                idle_timeout: None,
+                min_size: 1,
+                max_jobs: 0,
+                state_init: None,
+            },
+        }
+    }
+    /// The number of threads in the pool.
+    pub fn size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.size = size;
+        self
+    }
+    pub fn strategy(mut self, strategy: Strategy) -> ThreadPoolBuilder {
+        self.strategy = strategy;
+        self
+    }
+    /// Run the oldest or the newest queued job first, FIFO by default.
+    ///
+    /// With *Strategy::SharedQueue* LIFO takes the jobs off a shared stack instead of the
+    /// channel, with *Strategy::WorkStealing* every worker takes the newest job of its
+    /// own deque (and steals the oldest one of the others). Either way a terminating
+    /// worker only leaves once nothing is queued, so a shutdown still runs every job.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> ThreadPoolBuilder {
+        self.scheduling = scheduling;
+        self
+    }
+    /// Bound the number of jobs waiting for a worker, *execute* blocks (and
+    /// *try_execute* fails) while that many are queued. Unbounded by default.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+    /// Workers are named `{prefix}-{id}`, .e.g. `http-0`.
+    pub fn thread_name_prefix(mut self, prefix: &str) -> ThreadPoolBuilder {
+        self.settings.name_prefix = prefix.to_string();
+        self
+    }
+    /// The stack size of every worker thread in bytes, see *thread::Builder::stack_size*.
+    pub fn stack_size(mut self, bytes: usize) -> ThreadPoolBuilder {
+        self.settings.stack_size = Some(bytes);
+        self
+    }
+    /// Let a worker that went this long without a job exit, shrinking the pool down to
+    /// *min_size* during quiet periods. *ThreadPool::set_size* grows it back again.
+    ///
+    /// Workers waiting on the shared queue time out one after the other, so the pool
+    /// shrinks by one worker per timeout.
+    pub fn idle_timeout(mut self, timeout: Duration) -> ThreadPoolBuilder {
+        self.settings.idle_timeout = Some(timeout);
+        self
+    }
+    /// The number of workers idle ones never retire below, 1 by default and at least 1,
+    /// otherwise nobody would be left to pick up the next job.
+    pub fn min_size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.settings.min_size = size.max(1);
+        self
+    }
+    /// Replace a worker by a new thread once it ran this many jobs, so whatever a
+    /// long-lived thread accumulates (.e.g. leaked or fragmented memory, its worker
+    /// state) is given back now and then. The pool keeps its size, the replacement is
+    /// spawned before the old worker exits. 0, the default, keeps workers forever.
+    pub fn max_jobs_per_worker(mut self, jobs: usize) -> ThreadPoolBuilder {
+        self.settings.max_jobs = jobs;
+        self
+    }
+    /// Called on the worker with the panic payload whenever a job panics, after the
+    /// worker recovered from it.
+    pub fn panic_handler<F>(mut self, handler: F) -> ThreadPoolBuilder
+        where F: Fn(&(dyn Any + Send)) + Send + Sync + 'static, {
+            self.settings.panic_handler = RwLock::new(Some(Arc::new(handler)));
+            self
+        }
+    /// Give every worker a state of its own, created by *init* once when the worker
+    /// starts (also when *ThreadPool::set_size* adds one later) and dropped when it
+    /// exits. Jobs get at it through *ThreadPool::execute_with_state*, .e.g. to reuse a
+    /// database connection or a scratch buffer instead of creating one per job.
+    ///
+    /// Setting it again replaces the previous one.
+    pub fn worker_state<S, F>(mut self, init: F) -> ThreadPoolBuilder
+        where S: 'static, F: Fn() -> S + Send + Sync + 'static, {
+            let init: Box<StateInit> = Box::new(move || Box::new(init()));
+            self.settings.state_init = Some((TypeId::of::<S>(), init));
+            self
+        }
+    /// Create the pool.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolCreationError::ZeroSize` if the size is zero, or
+    /// `PoolCreationError::Spawn` if a worker thread can't be created.
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::from_builder(self)
+    }
+}
+impl Default for ThreadPoolBuilder {
+    fn default() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+}
+
+/// A cloneable handle on the counters of a pool.
+#[derive(Clone, Default)]
+pub struct PoolMetrics(Arc<Counters>);
+impl PoolMetrics {
+    // the counters are shared by the whole pool, so their address tells pools apart.
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            queued: self.0.queued.load(Ordering::SeqCst),
+            active: self.0.active.load(Ordering::SeqCst),
+            completed: self.0.completed.load(Ordering::SeqCst),
+            panicked: self.0.panicked.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    // notified whenever the last running job finishes with nothing queued, see *join*.
+    idle: Mutex<()>,
+    drained: Condvar,
+}
+
+// marks a job as active for as long as it's alive, dropping it (even while unwinding
+// from a panic) takes the job off the active count again.
+struct ActiveJob<'a>(&'a Counters);
+impl<'a> ActiveJob<'a> {
+    fn start(counters: &'a Counters) -> ActiveJob<'a> {
+        // active first, so the job is never counted as neither queued nor active.
+        counters.active.fetch_add(1, Ordering::SeqCst);
+        counters.queued.fetch_sub(1, Ordering::SeqCst);
+        ActiveJob(counters)
+    }
+}
+impl Drop for ActiveJob<'_> {
+    fn drop(&mut self) {
+        let counters = self.0;
+        if counters.active.fetch_sub(1, Ordering::SeqCst) == 1 && counters.queued.load(Ordering::SeqCst) == 0 {
+            // taking the lock makes sure a thread about to wait in *join* doesn't miss it.
+            let _idle = lock(&counters.idle);
+            counters.drained.notify_all();
+        }
+    }
+}
+
+// the sending half of the pool, see *Source* for the workers' half.
+enum Queue {
+    Shared(Sender),
+    Stealing(Arc<Deques>),
+    Stack(Arc<Stack>),
+}
+
+enum Sender {
+    Unbounded(mpsc::Sender<Message>),
+    Bounded(mpsc::SyncSender<Message>),
+}
+impl Sender {
+    // blocks while a bounded channel is full.
+    fn send(&self, message: Message) -> Result<(), mpsc::SendError<Message>> {
+        match self {
+            Sender::Unbounded(sender) => sender.send(message),
+            Sender::Bounded(sender) => sender.send(message),
+        }
+    }
+    fn try_send(&self, message: Message) -> Result<(), TrySendError<Message>> {
+        match self {
+            Sender::Unbounded(sender) => sender.send(message).map_err(|e| TrySendError::Disconnected(e.0)),
+            Sender::Bounded(sender) => sender.try_send(message),
+        }
+    }
+}
+
+// where a worker gets its next message from.
+#[derive(Clone)]
+enum Source {
+    Shared(Arc<Mutex<mpsc::Receiver<Message>>>),
+    Stealing(Arc<Deques>),
+    Stack(Arc<Stack>),
+}
+impl Source {
+    // *None* once the timeout passed without a message, *queue* is the worker's deque.
+    fn next(&self, queue: usize, timeout: Option<Duration>) -> Option<Message> {
+        match self {
+            // acquire the mutex first, and then block here waiting for a job.
+            // the ownership of the lock is based on the lifetime of the "MutexGuard<T>" that the method returns.
+            Source::Shared(receiver) => match timeout {
+                Some(timeout) => match lock(receiver).recv_timeout(timeout) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => panic!("the pool's sender is gone"),
+                },
+                None => Some(lock(receiver).recv().unwrap()),
+            },
+            Source::Stealing(deques) => deques.pop(queue, timeout.map(|timeout| Instant::now() + timeout)),
+            Source::Stack(stack) => stack.pop(timeout.map(|timeout| Instant::now() + timeout)),
+        }
+    }
+}
+
+// everything a worker needs, cloned into each one the pool spawns.
+#[derive(Clone)]
+struct WorkerContext {
+    source: Source,
+    metrics: PoolMetrics,
+    // a terminated worker sends its id, so a shrinking pool knows which thread to join.
+    exited: mpsc::Sender<usize>,
+    // same for a worker exiting after *idle_timeout*, reaped by *Workers::reap*.
+    retired: mpsc::Sender<usize>,
+    // a worker that ran *max_jobs* sends its id and the worker replacing it.
+    recycled: mpsc::Sender<(usize, Worker)>,
+    // ids are never reused, so a log line always points at a single thread.
+    next_id: Arc<Mutex<usize>>,
+    // workers running and not asked to terminate, what *ThreadPool::size* reports.
+    live: Arc<AtomicUsize>,
+    settings: Arc<WorkerSettings>,
+}
+
+impl WorkerContext {
+    fn next_id(&self) -> usize {
+        let mut next_id = lock(&self.next_id);
+        let id = *next_id;
+        *next_id += 1;
+        id
+    }
+    // the deque of a new worker for *Strategy::WorkStealing*, one an exited worker left
+    // behind if there is one. the other strategies have no deques, any index will do.
+    fn claim_queue(&self) -> usize {
+        match &self.source {
+            Source::Stealing(deques) => deques.claim(),
+            _ => 0,
+        }
+    }
+    fn release_queue(&self, queue: usize) {
+        if let Source::Stealing(deques) = &self.source {
+            deques.release(queue);
+        }
+    }
+}
+
+// one deque per worker for *Strategy::WorkStealing*, so workers only contend when stealing.
+struct Deques {
+    // never more than the pool had workers at once, a new worker takes over the deque of
+    // one that exited (and whatever jobs are still in it).
+    queues: RwLock<Vec<Deque>>,
+    // round-robin cursor picking the deque a new job goes to.
+    next: AtomicUsize,
+    // jobs sitting in any of the deques, lets idle workers sleep instead of spinning.
+    pending: AtomicUsize,
+    // workers asked to exit, honoured only once there's nothing left to run.
+    terminate: AtomicUsize,
+    // the most jobs *pending* may count, *None* for no limit.
+    capacity: Option<usize>,
+    // which end of its own deque a worker takes from, stealing uses the other one.
+    scheduling: Scheduling,
+    idle: Mutex<()>,
+    wakeup: Condvar,
+    // notified when a job is taken out of a full set of deques.
+    space: Condvar,
+}
+struct Deque {
+    jobs: Mutex<VecDeque<Job>>,
+    // whether a worker takes from it, see *Deques::claim*.
+    owned: AtomicBool,
+}
+impl Deques {
+    fn new(capacity: Option<usize>, scheduling: Scheduling) -> Deques {
+        Deques {
+            queues: RwLock::new(Vec::new()),
+            next: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            terminate: AtomicUsize::new(0),
+            capacity,
+            scheduling,
+            idle: Mutex::new(()),
+            wakeup: Condvar::new(),
+            space: Condvar::new(),
+        }
+    }
+    fn claim(&self) -> usize {
+        let mut queues = self.queues.write().unwrap_or_else(PoisonError::into_inner);
+        match queues.iter().position(|queue| !queue.owned.load(Ordering::SeqCst)) {
+            Some(index) => {
+                queues[index].owned.store(true, Ordering::SeqCst);
+                index
+            }
+            None => {
+                queues.push(Deque { jobs: Mutex::new(VecDeque::new()), owned: AtomicBool::new(true) });
+                queues.len() - 1
+            }
+        }
+    }
+    // the jobs left in it are stolen by the other workers meanwhile.
+    fn release(&self, index: usize) {
+        let queues = self.queues.read().unwrap_or_else(PoisonError::into_inner);
+        queues[index].owned.store(false, Ordering::SeqCst);
+    }
+    // waits for room first when the deques are bounded.
+    fn push(&self, job: Job) {
+        if let Some(capacity) = self.capacity {
+            let mut idle = lock(&self.idle);
+            while !self.reserve(capacity) {
+                idle = wait(&self.space, idle);
+            }
+            // *push_reserved* takes the lock again to wake a worker.
+            drop(idle);
+        } else {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+        }
+        self.push_reserved(job);
+    }
+    fn try_push(&self, job: Job) -> Result<(), Job> {
+        match self.capacity {
+            Some(capacity) if !self.reserve(capacity) => return Err(job),
+            Some(_) => {}
+            None => {
+                self.pending.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        self.push_reserved(job);
+        Ok(())
+    }
+    // count a job in *pending* if that stays within the capacity.
+    fn reserve(&self, capacity: usize) -> bool {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                if pending < capacity {
+                    Some(pending + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+    // the job was counted in *pending* already.
+    fn push_reserved(&self, job: Job) {
+        let queues = self.queues.read().unwrap_or_else(PoisonError::into_inner);
+        let size = queues.len();
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        // a deque nobody owns is only ever stolen from, skip it unless all of them are.
+        let index = (0..size)
+            .map(|offset| (next + offset) % size)
+            .find(|&index| queues[index].owned.load(Ordering::SeqCst))
+            .unwrap_or(next % size);
+        // the job was counted before it can be taken, so *pending* never goes below zero.
+        lock(&queues[index].jobs).push_back(job);
+        drop(queues);
+        // taking the lock makes sure a worker about to sleep doesn't miss the wakeup.
+        let _idle = lock(&self.idle);
+        self.wakeup.notify_one();
+    }
+    fn terminate(&self, count: usize) {
+        self.terminate.fetch_add(count, Ordering::SeqCst);
+        let _idle = lock(&self.idle);
+        self.wakeup.notify_all();
+    }
+    // *None* once the deadline passed with nothing to run.
+    fn pop(&self, queue: usize, deadline: Option<Instant>) -> Option<Message> {
+        loop {
+            if let Some(job) = self.take(queue) {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                if self.capacity.is_some() {
+                    // under the lock, so a pusher about to wait doesn't miss it.
+                    let _idle = lock(&self.idle);
+                    self.space.notify_one();
+                }
+                return Some(Message::NewJob(job));
+            }
+            let idle = lock(&self.idle);
+            if self.pending.load(Ordering::SeqCst) > 0 {
+                // a job is on its way into (or still in) some deque, go look again.
+                continue;
+            }
+            // nothing left to run, so a pending terminate can be honoured.
+            let terminated = self
+                .terminate
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| count.checked_sub(1));
+            if terminated.is_ok() {
+                return Some(Message::Terminate);
+            }
+            match deadline {
+                Some(deadline) => {
+                    let left = deadline.checked_duration_since(Instant::now())?;
+                    drop(wait_timeout(&self.wakeup, idle, left));
+                }
+                None => drop(wait(&self.wakeup, idle)),
+            }
+        }
+    }
+    // own deque first (oldest job, or newest with LIFO), then steal from the other end
+    // of another worker's deque.
+    fn take(&self, index: usize) -> Option<Job> {
+        let queues = self.queues.read().unwrap_or_else(PoisonError::into_inner);
+        let lifo = self.scheduling == Scheduling::Lifo;
+        let own = &queues[index].jobs;
+        let own = if lifo { lock(own).pop_back() } else { lock(own).pop_front() };
+        if own.is_some() {
+            return own;
+        }
+        let size = queues.len();
+        (1..size).map(|offset| (index + offset) % size).find_map(|victim| {
+            let mut victim = lock(&queues[victim].jobs);
+            if lifo {
+                victim.pop_front()
+            } else {
+                victim.pop_back()
+            }
+        })
+    }
+}
+
+// the shared queue of *Scheduling::Lifo*, the newest job is taken first.
+struct Stack {
+    jobs: Mutex<StackJobs>,
+    // the most jobs the stack holds, *None* for no limit.
+    capacity: Option<usize>,
+    // notified when a job is pushed or workers are asked to exit.
+    wakeup: Condvar,
+    // notified when a job is taken off a full stack.
+    space: Condvar,
+}
+struct StackJobs {
+    jobs: Vec<Job>,
+    // workers asked to exit, honoured only once there's nothing left to run.
+    terminate: usize,
+}
+impl Stack {
+    fn new(capacity: Option<usize>) -> Stack {
+        Stack {
+            jobs: Mutex::new(StackJobs { jobs: Vec::new(), terminate: 0 }),
+            capacity,
+            wakeup: Condvar::new(),
+            space: Condvar::new(),
+        }
+    }
+    // waits for room first when the stack is bounded.
+    fn push(&self, job: Job) {
+        let mut stack = lock(&self.jobs);
+        while self.capacity.is_some_and(|capacity| stack.jobs.len() >= capacity) {
+            stack = wait(&self.space, stack);
+        }
+        stack.jobs.push(job);
+        self.wakeup.notify_one();
+    }
+    fn try_push(&self, job: Job) -> Result<(), Job> {
+        let mut stack = lock(&self.jobs);
+        if self.capacity.is_some_and(|capacity| stack.jobs.len() >= capacity) {
+            return Err(job);
+        }
+        stack.jobs.push(job);
+        self.wakeup.notify_one();
+        Ok(())
+    }
+    fn terminate(&self, count: usize) {
+        lock(&self.jobs).terminate += count;
+        self.wakeup.notify_all();
+    }
+    // *None* once the deadline passed with nothing to run.
+    fn pop(&self, deadline: Option<Instant>) -> Option<Message> {
+        let mut stack = lock(&self.jobs);
+        loop {
+            if let Some(job) = stack.jobs.pop() {
+                if self.capacity.is_some() {
+                    self.space.notify_one();
+                }
+                return Some(Message::NewJob(job));
+            }
+            if stack.terminate > 0 {
+                stack.terminate -= 1;
+                return Some(Message::Terminate);
+            }
+            stack = match deadline {
+                Some(deadline) => {
+                    let left = deadline.checked_duration_since(Instant::now())?;
+                    wait_timeout(&self.wakeup, stack, left)
+                }
+                None => wait(&self.wakeup, stack),
+            };
+        }
+    }
+}
+
+// a panic while one of the pool's locks is held mustn't take every other worker down
+// with it, the data behind them stays consistent so the poison is only logged.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        log(Level::Warn, "Recovered a poisoned lock.");
+        poisoned.into_inner()
+    })
+}
+
+// *Condvar::wait* with the same recovery as *lock*.
+fn wait<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    condvar.wait(guard).unwrap_or_else(|poisoned| {
+        log(Level::Warn, "Recovered a poisoned lock.");
+        poisoned.into_inner()
+    })
+}
+
+// *Condvar::wait_timeout*, whether it timed out is up to the caller to find out.
+fn wait_timeout<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>, timeout: Duration) -> MutexGuard<'a, T> {
+    match condvar.wait_timeout(guard, timeout) {
+        Ok((guard, _)) => guard,
+        Err(poisoned) => {
+            log(Level::Warn, "Recovered a poisoned lock.");
+            poisoned.into_inner().0
+        }
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+impl Worker {
+    // *queue* comes from *WorkerContext::claim_queue*, and is released when the worker exits
+    // other than by being recycled, its replacement takes the deque over.
+    fn new(id: usize, queue: usize, context: WorkerContext) -> io::Result<Worker> {
+        // named, so the worker shows up in panic messages and debuggers.
+        let mut builder = thread::Builder::new().name(format!("{}-{}", context.settings.name_prefix, id));
+        if let Some(stack_size) = context.settings.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let thread = builder.spawn(move || {
+            let WorkerContext { source, metrics, exited, retired, live, settings, .. } = &context;
+            // lets *fork_join* tell it's being called from one of this pool's own jobs.
+            CURRENT_POOL.with(|pool| pool.set(metrics.id()));
+            if let Some((_, init)) = &settings.state_init {
+                // the worker keeps going without, only the jobs needing the state fail.
+                match panic::catch_unwind(AssertUnwindSafe(&**init)) {
+                    Ok(state) => WORKER_STATE.with(|slot| *slot.borrow_mut() = Some(state)),
+                    Err(_) => log(Level::Error, &format!("Worker {} failed to create its state.", id)),
+                }
+            }
+            let mut jobs = 0;
+            loop {
+                let message = match source.next(queue, settings.idle_timeout) {
+                    Some(message) => message,
+                    None => {
+                        // idle for *idle_timeout*, leave unless that would go below *min_size*.
+                        let min_size = settings.min_size;
+                        let leaving = live.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                            if live > min_size {
+                                Some(live - 1)
+                            } else {
+                                None
+                            }
+                        });
+                        if leaving.is_ok() {
+                            log(Level::Debug, &format!("Worker {} is idle; retiring.", id));
+                            context.release_queue(queue);
+                            let _ = retired.send(id);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                match message {
+                    Message::NewJob(job) => {
+                        log(Level::Debug, &format!("Worker {} got a job; executing.", id));
+                        let active = ActiveJob::start(&metrics.0);
+                        // a panicking job must not take the worker down with it, nothing is
+                        // shared between the job and the loop so it's fine to assert unwind safety.
+                        let result = panic::catch_unwind(AssertUnwindSafe(job));
+                        match result {
+                            Ok(()) => {
+                                let skipped = SKIPPED.with(Cell::take);
+                                metrics.0.completed.fetch_add(u64::from(!skipped), Ordering::SeqCst)
+                            }
+                            Err(payload) => {
+                                // the thread name (.e.g. "worker-3") is part of every log line.
+                                log(Level::Error, &format!("Worker {} recovered from a panicking job.", id));
+                                // cloned out, so *on_panic* isn't held up while the handler runs.
+                                let handler = settings.panic_handler.read().map_or_else(
+                                    |poisoned| poisoned.into_inner().clone(),
+                                    |handler| handler.clone(),
+                                );
+                                if let Some(handler) = handler {
+                                    // a panicking handler would take the worker down after all.
+                                    let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(&*payload)));
+                                }
+                                metrics.0.panicked.fetch_add(1, Ordering::SeqCst)
+                            }
+                        };
+                        // only now, so *join* never returns before the job is counted.
+                        drop(active);
+                        jobs += 1;
+                        if settings.max_jobs > 0 && jobs >= settings.max_jobs {
+                            let replacement_id = context.next_id();
+                            match Worker::new(replacement_id, queue, context.clone()) {
+                                Ok(replacement) => {
+                                    log(Level::Info, &format!(
+                                        "Worker {} recycled after {} jobs; replaced by worker {}.",
+                                        id, jobs, replacement_id
+                                    ));
+                                    let _ = context.recycled.send((id, replacement));
+                                    break;
+                                }
+                                // keep going rather than shrink the pool, and try again after the next job.
+                                Err(e) => log(Level::Error, &format!("Worker {} can't be replaced: {}", id, e)),
+                            }
+                        }
+                    }
+                    Message::Terminate => {
+                        log(Level::Info, &format!("Worker {} was told to terminate.", id));
+                        context.release_queue(queue);
+                        // nobody listens once the pool is gone, that's fine.
+                        let _ = exited.send(id);
+                        break;
+                    }
+                }
+            }
+        })?;
+        Ok(Worker { id, thread: Some(thread) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // hold the lock while panicking, .e.g. like a thread that died in *set_size*.
+    fn poison<T>(mutex: &Mutex<T>) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = mutex.lock().unwrap();
+            panic!("poisoning the lock");
+        }));
+        assert!(mutex.is_poisoned());
+    }
+
+    #[test]
+    fn shutdown_goes_through_a_poisoned_worker_list() {
+        let mut pool = ThreadPool::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        poison(&pool.workers);
+        pool.shutdown();
+        assert_eq!(done.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn shutdown_timeout_goes_through_a_poisoned_worker_list() {
+        let mut pool = ThreadPool::new(2);
+        poison(&pool.workers);
+        assert_eq!(pool.shutdown_timeout(Duration::from_secs(5)), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn jobs_keep_running_through_a_poisoned_receiver() {
+        let mut pool = ThreadPool::new(1);
+        // an idle worker holds the receiver's lock, the only one is kept busy meanwhile.
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || blocked.recv().unwrap()).unwrap();
+        while pool.stats().active == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        match &pool.context.source {
+            Source::Shared(receiver) => poison(receiver),
+            _ => unreachable!(),
+        }
+        release.send(()).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..20 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(done.load(Ordering::SeqCst), 20);
+        assert_eq!(pool.stats().completed, 21);
+    }
+
+    #[test]
+    fn a_pool_of_zero_threads_is_an_error() {
+        let e = ThreadPool::build(0).err().expect("a pool without workers");
+        assert!(matches!(e, PoolCreationError::ZeroSize));
+        assert_eq!(e.to_string(), "thread pool size must be greater than zero");
+        assert!(e.source().is_none());
+        assert!(ThreadPool::builder().size(0).build().is_err());
+        assert_eq!(ThreadPool::build(1).unwrap().size(), 1);
+    }
+
+    // the throughput of the two strategies, compare them with "--nocapture".
+    #[test]
+    fn ten_thousand_jobs_run_under_both_strategies() {
+        for strategy in [Strategy::SharedQueue, Strategy::WorkStealing] {
+            let pool = ThreadPool::with_strategy(4, strategy).unwrap();
+            let done = Arc::new(AtomicUsize::new(0));
+            for _ in 0..10_000 {
+                let done = Arc::clone(&done);
+                pool.execute(move || {
+                    done.fetch_add(1, Ordering::Relaxed);
+                })
+                .unwrap();
+            }
+            pool.join();
+            assert_eq!(done.load(Ordering::SeqCst), 10_000);
+            assert_eq!(pool.stats().completed, 10_000);
+        }
+    }
+
+    #[test]
+    fn a_worker_keeps_going_after_a_job_panics() {
+        // a single worker, so the second job runs on the one the first took down.
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicBool::new(false));
+        pool.execute(|| panic!("a job gone wrong")).unwrap();
+        let second = Arc::clone(&ran);
+        pool.execute(move || second.store(true, Ordering::SeqCst)).unwrap();
+        pool.join();
+        assert!(ran.load(Ordering::SeqCst));
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.panicked), (1, 1));
+        assert_eq!(pool.size(), 1);
+    }
+
+    // every job waits (for a while at most) until all of them are running at once.
+    fn run_together(pool: &ThreadPool, jobs: usize) -> usize {
+        let started = Arc::new(AtomicUsize::new(0));
+        let together = Arc::new(AtomicUsize::new(0));
+        for _ in 0..jobs {
+            let (started, together) = (Arc::clone(&started), Arc::clone(&together));
+            pool.execute(move || {
+                started.fetch_add(1, Ordering::SeqCst);
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while started.load(Ordering::SeqCst) < jobs && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                if started.load(Ordering::SeqCst) == jobs {
+                    together.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        }
+        pool.join();
+        together.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn a_grown_pool_runs_more_jobs_at_once() {
+        let pool = ThreadPool::new(2);
+        pool.set_size(4).unwrap();
+        assert_eq!(pool.size(), 4);
+        assert_eq!(run_together(&pool, 4), 4);
+        pool.set_size(1).unwrap();
+        assert_eq!(pool.size(), 1);
+        assert!(pool.set_size(0).is_err());
+    }
+
+    // "{prefix}-{id}" with a numeric id.
+    fn is_worker_name(name: &str, prefix: &str) -> bool {
+        name.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit()))
+    }
+
+    #[test]
+    fn jobs_run_on_named_workers() {
+        let pool = ThreadPool::new(2);
+        let name = pool.submit(|| thread::current().name().map(str::to_string)).join().unwrap();
+        let name = name.expect("a named worker");
+        assert!(is_worker_name(&name, "worker"), "{}", name);
+    }
+
+    #[test]
+    fn join_waits_for_every_queued_job() {
+        let pool = ThreadPool::new(4);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..50 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(2));
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.join();
+        assert_eq!(done.load(Ordering::SeqCst), 50);
+        assert_eq!(pool.stats().queued, 0);
+        assert_eq!(pool.stats().active, 0);
+        // nothing to wait for.
+        pool.join();
+    }
+
+    #[test]
+    fn a_handle_returns_what_the_job_returned() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.submit(|| 21 * 2);
+        assert_eq!(handle.join().unwrap(), 42);
+        let handle = pool.submit(|| -> u32 { panic!("no answer") });
+        assert_eq!(handle.join(), Err(JobError::Panicked));
+        let handles: Vec<JobHandle<String>> = (0..4).map(|i| pool.submit(move || i.to_string())).collect();
+        let results: Vec<String> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(results, ["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn the_builder_names_workers_and_sizes_their_stacks() {
+        let pool = ThreadPool::builder().size(2).thread_name_prefix("http").stack_size(16 << 20).build().unwrap();
+        let name = pool.submit(|| thread::current().name().map(str::to_string)).join().unwrap();
+        assert!(is_worker_name(name.as_deref().unwrap_or(""), "http"), "{:?}", name);
+        // more than the 2MB a thread gets by default.
+        let sum = pool.submit(|| {
+            let buffer = std::hint::black_box([1u8; 3 << 20]);
+            buffer.iter().map(|&byte| u64::from(byte)).sum::<u64>()
+        });
+        assert_eq!(sum.join().unwrap(), 3 << 20);
+    }
+
+    #[test]
+    fn jobs_are_turned_away_once_shutdown_started() {
+        let mut pool = ThreadPool::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&done);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(20));
+            counted.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+        pool.shutdown();
+        // the job submitted before still ran.
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.execute(|| {}), Err(ExecuteError::ShuttingDown));
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::ShuttingDown));
+        assert_eq!(pool.submit(|| 1).join(), Err(JobError::Lost));
+        // a second shutdown has nothing left to do.
+        pool.shutdown();
+    }
+
+    // wait (for a while at most) until the pool picked up that many jobs.
+    fn wait_active(pool: &ThreadPool, jobs: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().active < jobs && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pool.stats().active, jobs);
+    }
+
+    #[test]
+    fn try_execute_reports_a_full_queue() {
+        let pool = ThreadPool::builder().size(1).queue_capacity(1).build().unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || blocked.recv().unwrap()).unwrap();
+        wait_active(&pool, 1);
+        // the worker is busy, so this one takes the only slot.
+        assert_eq!(pool.try_execute(|| {}), Ok(()));
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::Full));
+        assert_eq!(pool.stats().queued, 1);
+        release.send(()).unwrap();
+        pool.join();
+        assert_eq!(pool.stats().completed, 2);
+    }
+
+    #[test]
+    fn shutdown_timeout_gives_up_on_a_busy_worker() {
+        let mut pool = ThreadPool::new(2);
+        let (release, blocked) = mpsc::channel::<()>();
+        let name = pool.submit(move || {
+            let name = thread::current().name().map(str::to_string);
+            blocked.recv().unwrap();
+            name
+        });
+        wait_active(&pool, 1);
+        let started = Instant::now();
+        let late = pool.shutdown_timeout(Duration::from_millis(100));
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+        // the idle worker was joined, only the busy one is reported.
+        release.send(()).unwrap();
+        let name = name.join().unwrap().expect("a named worker");
+        assert_eq!(late.iter().map(|id| format!("worker-{}", id)).collect::<Vec<_>>(), [name]);
+    }
+
+    #[test]
+    fn idle_workers_retire_down_to_the_minimum() {
+        let pool = ThreadPool::builder().size(4).min_size(2).idle_timeout(Duration::from_millis(20)).build().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.size() > 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(pool.size(), 2);
+        // and no further, however long it stays idle.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.submit(|| 7).join(), Ok(7));
+        pool.set_size(4).unwrap();
+        assert_eq!(pool.size(), 4);
+    }
+
+    #[test]
+    fn fork_join_keeps_the_order_of_the_items() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let squares = pool.fork_join((0..100u64).collect(), |n| n * n);
+        assert_eq!(squares, (0..100u64).map(|n| n * n).collect::<Vec<_>>());
+        assert_eq!(pool.fork_join(Vec::<u64>::new(), |n| n), Vec::<u64>::new());
+        // called from a job of the same pool, it doesn't wait for the busy workers.
+        let inner = Arc::clone(&pool);
+        let sums = pool.submit(move || inner.fork_join(vec![1, 2, 3], |n: u64| n + 1));
+        assert_eq!(sums.join().unwrap(), [2, 3, 4]);
+    }
+
+    #[test]
+    fn every_worker_counts_in_its_own_state() {
+        let inits = Arc::new(AtomicUsize::new(0));
+        let created = Arc::clone(&inits);
+        let pool = ThreadPool::builder()
+            .size(3)
+            .worker_state(move || {
+                created.fetch_add(1, Ordering::SeqCst);
+                0u32
+            })
+            .build()
+            .unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..300 {
+            let seen = Arc::clone(&seen);
+            pool.execute_with_state(move |count: &mut u32| {
+                *count += 1;
+                let name = thread::current().name().unwrap_or("").to_string();
+                seen.lock().unwrap().push((name, *count));
+            })
+            .unwrap();
+        }
+        pool.join();
+        assert_eq!(inits.load(Ordering::SeqCst), 3);
+        // each worker's counter went up by one per job it ran, whatever the others did.
+        let mut per_worker: HashMap<String, Vec<u32>> = HashMap::new();
+        for (name, count) in seen.lock().unwrap().drain(..) {
+            per_worker.entry(name).or_default().push(count);
+        }
+        assert!(per_worker.len() <= 3);
+        for counts in per_worker.values() {
+            assert_eq!(*counts, (1..=counts.len() as u32).collect::<Vec<_>>());
+        }
+        assert_eq!(per_worker.values().map(Vec::len).sum::<usize>(), 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "the pool has no worker state of type")]
+    fn a_state_of_another_type_is_a_panic() {
+        let pool = ThreadPool::builder().size(1).worker_state(|| 0u32).build().unwrap();
+        let _ = pool.execute_with_state(|_: &mut String| {});
+    }
+
+    // the order a single busy worker gets to the jobs queued behind it in.
+    fn queued_order(scheduling: Scheduling) -> Vec<usize> {
+        let mut pool = ThreadPool::builder().size(1).scheduling(scheduling).build().unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || blocked.recv().unwrap()).unwrap();
+        wait_active(&pool, 1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for sequence in 1..=5 {
+            let order = Arc::clone(&order);
+            pool.execute(move || order.lock().unwrap().push(sequence)).unwrap();
+        }
+        release.send(()).unwrap();
+        // the queued jobs still all run while shutting down.
+        pool.shutdown();
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    #[test]
+    fn lifo_runs_the_newest_job_first() {
+        assert_eq!(queued_order(Scheduling::Lifo), [5, 4, 3, 2, 1]);
+        assert_eq!(queued_order(Scheduling::Fifo), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn the_panic_callback_fires_once_per_panic() {
+        let pool = ThreadPool::new(2);
+        let fired = Arc::new(AtomicUsize::new(0));
+        let message = Arc::new(Mutex::new(String::new()));
+        let (counted, seen) = (Arc::clone(&fired), Arc::clone(&message));
+        pool.on_panic(move |payload| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            *seen.lock().unwrap() = payload.downcast_ref::<&str>().unwrap_or(&"").to_string();
+        });
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| panic!("job 2 failed")).unwrap();
+        pool.join();
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+        assert_eq!(*message.lock().unwrap(), "job 2 failed");
+
+        // one that panics itself doesn't take the worker down either.
+        pool.on_panic(|_| panic!("the callback failed too"));
+        pool.execute(|| panic!("job 3 failed")).unwrap();
+        pool.join();
+        assert_eq!(pool.submit(|| 3).join(), Ok(3));
+        assert_eq!((pool.stats().panicked, pool.size()), (2, 2));
+    }
+
+    #[test]
+    fn a_worker_is_replaced_after_its_last_job() {
+        let pool = ThreadPool::builder().size(1).max_jobs_per_worker(3).build().unwrap();
+        // one after the other, so they all run on the only worker there is at the time.
+        let run = || pool.submit(|| thread::current().id()).join().unwrap();
+        let threads: Vec<thread::ThreadId> = (0..7).map(|_| run()).collect();
+        assert_eq!(threads[0], threads[2]);
+        assert_ne!(threads[2], threads[3]);
+        assert_eq!(threads[3], threads[5]);
+        assert_ne!(threads[5], threads[6]);
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn replacement_workers_take_over_the_deques_of_the_ones_gone() {
+        let pool = ThreadPool::builder()
+            .size(2)
+            .strategy(Strategy::WorkStealing)
+            .max_jobs_per_worker(1)
+            .build()
+            .unwrap();
+        let deques = |pool: &ThreadPool| match &pool.context.source {
+            Source::Stealing(deques) => deques.queues.read().unwrap().len(),
+            _ => unreachable!(),
+        };
+        // every job is followed by a new worker.
+        for n in 0..100 {
+            assert_eq!(pool.submit(move || n).join(), Ok(n));
+        }
+        assert_eq!(deques(&pool), 2);
+        // terminated ones leave theirs behind for the workers spawned next.
+        pool.set_size(1).unwrap();
+        pool.set_size(3).unwrap();
+        assert_eq!(deques(&pool), 3);
+        pool.join();
+        assert_eq!(pool.stats().completed, 100);
+    }
+
+    #[test]
+    fn cancelled_jobs_are_skipped_or_give_up() {
+        let pool = ThreadPool::new(1);
+        let (started, running) = mpsc::channel::<()>();
+        // running, and checking its token until told to stop.
+        let (busy, busy_token) = pool.submit_cancellable(move |token| {
+            started.send(()).unwrap();
+            let mut rounds = 0u64;
+            while !token.is_cancelled() {
+                rounds += 1;
+                thread::sleep(Duration::from_millis(1));
+            }
+            rounds
+        });
+        running.recv().unwrap();
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&ran);
+        let (queued, queued_token) = pool.submit_cancellable(move |_| flag.store(true, Ordering::SeqCst));
+        queued_token.cancel();
+        busy_token.cancel();
+        assert!(busy.join().is_ok());
+        assert_eq!(queued.join(), Err(JobError::Cancelled));
+        assert!(!ran.load(Ordering::SeqCst));
+
+        // one whose deadline passed while queued is skipped the same way.
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || blocked.recv().unwrap()).unwrap();
+        let (late, token) = pool.submit_cancellable(|_| 1);
+        token.cancel_at(Instant::now());
+        release.send(()).unwrap();
+        assert_eq!(late.join(), Err(JobError::Cancelled));
+        let (on_time, token) = pool.submit_cancellable(|_| 2);
+        token.cancel_at(Instant::now() + Duration::from_secs(60));
+        assert_eq!(on_time.join(), Ok(2));
+    }
+
+    #[test]
+    fn a_job_cancelled_while_queued_is_not_counted_as_completed() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || blocked.recv().unwrap()).unwrap();
+        while pool.stats().active == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (skipped, token) = pool.submit_cancellable(|_| 1);
+        token.cancel();
+        // still queued until the worker gets to it.
+        assert_eq!(pool.stats().queued, 1);
+        release.send(()).unwrap();
+        assert_eq!(skipped.join(), Err(JobError::Cancelled));
+        pool.join();
+        let stats = pool.stats();
+        assert_eq!((stats.queued, stats.completed, stats.panicked), (0, 1, 0));
+
+        let (ran, _) = pool.submit_cancellable(|_| 2);
+        assert_eq!(ran.join(), Ok(2));
+        pool.join();
+        assert_eq!(pool.stats().completed, 2);
+    }
+}
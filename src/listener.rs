@@ -0,0 +1,384 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(not(target_os = "linux"))]
+use crate::log::{log, Level};
+use crate::server::Stream;
+
+/// A bound socket the server accepts connections on.
+///
+/// A Unix domain socket removes its file again when dropped.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// An accepted connection, from either kind of *Listener*.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Bind to `tcp://host:port`, `unix:///path/to/socket` or a plain `host:port`,
+    /// which is TCP.
+    ///
+    /// A socket file left behind at the path (.e.g. by a crashed server) is replaced.
+    /// *reuse_address* sets `SO_REUSEADDR` on a TCP socket before binding it, so a
+    /// restarted server doesn't have to wait for the old connections to time out.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the address can't be bound, or an `InvalidInput` error
+    /// for a Unix socket on a platform without them.
+    pub fn bind(addr: &str, reuse_address: bool) -> io::Result<Listener> {
+        if let Some(path) = addr.strip_prefix("unix://") {
+            return Listener::bind_unix(path);
+        }
+        let addr = addr.strip_prefix("tcp://").unwrap_or(addr);
+        // std always sets "SO_REUSEADDR" on Unix, a socket without it has to be made by hand.
+        #[cfg(target_os = "linux")]
+        {
+            if !reuse_address {
+                return sys::bind_without_reuse(addr).map(Listener::Tcp);
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            if !reuse_address {
+                log(Level::Warn, "SO_REUSEADDR can't be turned off on this platform.");
+            }
+        }
+        TcpListener::bind(addr).map(Listener::Tcp)
+    }
+    #[cfg(unix)]
+    fn bind_unix(path: &str) -> io::Result<Listener> {
+        let path = PathBuf::from(path);
+        // only ever a stale socket, never a regular file that happens to be there.
+        if let Ok(metadata) = fs::symlink_metadata(&path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(&path)?;
+            }
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Listener::Unix(listener, path))
+    }
+    #[cfg(not(unix))]
+    fn bind_unix(_path: &str) -> io::Result<Listener> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "unix domain sockets aren't supported here"))
+    }
+    /// The address the TCP socket is bound to, .e.g. to find out which port was picked
+    /// for `127.0.0.1:0`. A Unix domain socket has none.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+    /// Accept the next connection, see `TcpListener::accept`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error of the underlying listener, `WouldBlock` when it's
+    /// non-blocking and nobody is waiting.
+    pub fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Connection::Unix(stream)),
+        }
+    }
+}
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            // nobody can connect anymore, don't leave the file lying around.
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Connection {
+    /// Turn Nagle's algorithm off (or back on), see `TcpStream::set_nodelay`. A Unix
+    /// domain socket has nothing to turn off.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Connection::Unix(_) => Ok(()),
+        }
+    }
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+    /// Look at the bytes waiting on the connection without consuming them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error of the underlying stream, an `Unsupported` error for a
+    /// Unix domain socket which can't be peeked at.
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.peek(buf),
+            #[cfg(unix)]
+            Connection::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "can't peek at a unix socket")),
+        }
+    }
+}
+#[cfg(unix)]
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Connection::Tcp(stream) => stream.as_raw_fd(),
+            Connection::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+impl Stream for Connection {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+    // a Unix socket peer has no IP address, so it's left unknown.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Connection::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            Connection::Unix(_) => None,
+        }
+    }
+}
+
+// binding by hand, the same as std does except for "SO_REUSEADDR".
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::FromRawFd;
+
+    const AF_INET: c_int = 2;
+    const AF_INET6: c_int = 10;
+    const SOCK_STREAM: c_int = 1;
+    const SOCK_CLOEXEC: c_int = 0o2000000;
+    // the backlog std uses too.
+    const BACKLOG: c_int = 128;
+
+    extern "C" {
+        fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        fn listen(fd: c_int, backlog: c_int) -> c_int;
+        fn close(fd: c_int) -> c_int;
+    }
+
+    #[repr(C)]
+    struct SockaddrIn {
+        family: u16,
+        port: u16,
+        addr: [u8; 4],
+        zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    struct SockaddrIn6 {
+        family: u16,
+        port: u16,
+        flowinfo: u32,
+        addr: [u8; 16],
+        scope_id: u32,
+    }
+
+    // -1 means the call failed, the reason is in errno.
+    fn check(result: c_int) -> io::Result<c_int> {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    pub fn bind_without_reuse(addr: &str) -> io::Result<TcpListener> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to");
+        // like *TcpListener::bind*, the first address that binds wins.
+        for addr in addr.to_socket_addrs()? {
+            match bind_one(addr) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn bind_one(addr: SocketAddr) -> io::Result<TcpListener> {
+        let domain = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+        // the descriptor is closed again on every error below.
+        let fd = check(unsafe { socket(domain, SOCK_STREAM | SOCK_CLOEXEC, 0) })?;
+        let bound = match addr {
+            SocketAddr::V4(addr) => {
+                let raw = SockaddrIn {
+                    family: AF_INET as u16,
+                    port: addr.port().to_be(),
+                    addr: addr.ip().octets(),
+                    zero: [0; 8],
+                };
+                unsafe { bind(fd, &raw as *const SockaddrIn as *const c_void, mem::size_of::<SockaddrIn>() as u32) }
+            }
+            SocketAddr::V6(addr) => {
+                let raw = SockaddrIn6 {
+                    family: AF_INET6 as u16,
+                    port: addr.port().to_be(),
+                    // unlike the port, std keeps it in network byte order already.
+                    flowinfo: addr.flowinfo(),
+                    addr: addr.ip().octets(),
+                    scope_id: addr.scope_id(),
+                };
+                unsafe { bind(fd, &raw as *const SockaddrIn6 as *const c_void, mem::size_of::<SockaddrIn6>() as u32) }
+            }
+        };
+        if let Err(e) = check(bound).and_then(|_| check(unsafe { listen(fd, BACKLOG) })) {
+            unsafe { close(fd) };
+            return Err(e);
+        }
+        // the listener owns the descriptor from here on, and closes it when dropped.
+        Ok(unsafe { TcpListener::from_raw_fd(fd) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a client connected to the listener, and the connection the listener accepted for it.
+    fn connect(listener: &Listener) -> (TcpStream, Connection) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn reuse_address(listener: &Listener) -> bool {
+        use std::os::raw::{c_int, c_void};
+        extern "C" {
+            fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32) -> c_int;
+        }
+        const SOL_SOCKET: c_int = 1;
+        const SO_REUSEADDR: c_int = 2;
+        let (mut value, mut len): (c_int, u32) = (0, 4);
+        let value_ptr = &mut value as *mut c_int as *mut c_void;
+        let result = unsafe { getsockopt(listener.as_raw_fd(), SOL_SOCKET, SO_REUSEADDR, value_ptr, &mut len) };
+        assert_eq!(result, 0);
+        value != 0
+    }
+
+    #[test]
+    fn an_accepted_connection_takes_nodelay() {
+        let listener = Listener::bind("127.0.0.1:0", true).unwrap();
+        for nodelay in [true, false] {
+            let (_client, connection) = connect(&listener);
+            connection.set_nodelay(nodelay).unwrap();
+            match connection {
+                Connection::Tcp(stream) => assert_eq!(stream.nodelay().unwrap(), nodelay),
+                #[cfg(unix)]
+                Connection::Unix(_) => panic!("not a TCP connection"),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reuse_address_can_be_turned_off() {
+        let with_reuse = Listener::bind("tcp://127.0.0.1:0", true).unwrap();
+        assert!(reuse_address(&with_reuse));
+        let without_reuse = Listener::bind("tcp://127.0.0.1:0", false).unwrap();
+        assert!(!reuse_address(&without_reuse));
+        let (mut client, mut connection) = connect(&without_reuse);
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        connection.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn an_ipv6_listener_binds_without_reuse() {
+        let listener = match Listener::bind("[::1]:0", false) {
+            Ok(listener) => listener,
+            // no IPv6 loopback in this environment.
+            Err(_) => return,
+        };
+        assert!(!reuse_address(&listener));
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.is_ipv6() && addr.port() != 0);
+        let (_client, connection) = connect(&listener);
+        assert_eq!(connection.peer_addr().map(|peer| peer.ip()), Some(addr.ip()));
+    }
+}
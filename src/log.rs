@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use crate::date::UtcDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+impl Level {
+    /// Parse a level name like `debug` or `WARN`.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+// the lowest level that gets printed, read from "LOG_LEVEL" the first time it's needed.
+static THRESHOLD: AtomicUsize = AtomicUsize::new(UNSET);
+const UNSET: usize = usize::MAX;
+const LEVELS: [Level; 4] = [Level::Debug, Level::Info, Level::Warn, Level::Error];
+
+/// Override the level set through the `LOG_LEVEL` environment variable.
+pub fn set_level(level: Level) {
+    THRESHOLD.store(level as usize, Ordering::Relaxed);
+}
+
+fn threshold() -> Level {
+    let mut index = THRESHOLD.load(Ordering::Relaxed);
+    if index == UNSET {
+        // unset or unknown values keep the per-job chatter out.
+        let level = env::var("LOG_LEVEL")
+            .ok()
+            .and_then(|name| Level::parse(&name))
+            .unwrap_or(Level::Info);
+        index = level as usize;
+        THRESHOLD.store(index, Ordering::Relaxed);
+    }
+    LEVELS[index]
+}
+
+thread_local! {
+    // the id of the request being handled on this thread, empty between requests.
+    static REQUEST_ID: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Tags every line logged on this thread with a request id, until dropped.
+pub struct RequestScope(());
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|id| id.borrow_mut().clear());
+    }
+}
+
+/// Include *id* in the lines logged on this thread while the returned scope is alive,
+/// .e.g. `[worker-3] [3f2a9c1b7d4e5a60] ...`. An empty id tags nothing.
+pub fn request_scope(id: &str) -> RequestScope {
+    REQUEST_ID.with(|current| *current.borrow_mut() = id.to_string());
+    RequestScope(())
+}
+
+/// Whether messages at this level are printed at all.
+pub fn enabled(level: Level) -> bool {
+    level >= threshold()
+}
+
+/// Print a timestamped line to stderr, if the level passes the `LOG_LEVEL` threshold.
+///
+/// The name of the current thread is included, so lines logged from a pool job carry
+/// the id of the worker that ran it (.e.g. `worker-3`), and so is the id of the request
+/// being handled, see *request_scope*.
+pub fn log(level: Level, msg: &str) {
+    if !enabled(level) {
+        return;
+    }
+    let current = thread::current();
+    // the thread-local may already be gone while the thread exits.
+    let request = REQUEST_ID
+        .try_with(|id| {
+            let id = id.borrow();
+            if id.is_empty() {
+                String::new()
+            } else {
+                format!("[{}] ", id)
+            }
+        })
+        .unwrap_or_default();
+    let line = format!(
+        "{} {:5} [{}] {}{}\n",
+        UtcDateTime::now().to_iso8601(),
+        level.as_str(),
+        current.name().unwrap_or("-"),
+        request,
+        msg
+    );
+    // a single write keeps lines from different threads from interleaving.
+    let _ = io::stderr().write_all(line.as_bytes());
+}
@@ -0,0 +1,155 @@
+use crate::http::{self, HeaderMap};
+
+/// One part of a `multipart/form-data` body, a form field or an uploaded file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    // like the headers of a *Request*.
+    pub headers: HeaderMap,
+    // the form field, from the "name" parameter of "Content-Disposition".
+    pub name: String,
+    // only there for an uploaded file.
+    pub filename: Option<String>,
+    pub content: Vec<u8>,
+}
+
+/// The boundary parameter of a `multipart/form-data` content type, .e.g. `xyz` of
+/// `multipart/form-data; boundary=xyz`.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let media_type = content_type.split(';').next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameter(content_type, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a `multipart/form-data` body into its parts.
+///
+/// Everything before the first boundary (the preamble) and after the closing
+/// `--boundary--` is ignored. Parts without a `Content-Disposition` naming the field
+/// are skipped, and so is a part the body ends in the middle of.
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Vec<Part> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    // the content of a part ends at the CRLF in front of the next delimiter.
+    let mut next = b"\r\n".to_vec();
+    next.extend_from_slice(&delimiter);
+    let mut parts = Vec::new();
+    let mut rest = match find(body, &delimiter) {
+        Some(start) => &body[start + delimiter.len()..],
+        None => return parts,
+    };
+    loop {
+        // "--" right after a delimiter closes the body.
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let after_line = match find(rest, b"\r\n") {
+            Some(end) => &rest[end + 2..],
+            None => break,
+        };
+        let (part, remaining) = match find(after_line, &next) {
+            Some(end) => (&after_line[..end], &after_line[end + next.len()..]),
+            None => break,
+        };
+        if let Some(part) = parse_part(part) {
+            parts.push(part);
+        }
+        rest = remaining;
+    }
+    parts
+}
+
+fn parse_part(raw: &[u8]) -> Option<Part> {
+    // a part without headers starts with the blank line right away.
+    let (head, content) = if raw.starts_with(b"\r\n") {
+        (&raw[..0], &raw[2..])
+    } else {
+        let end = find(raw, b"\r\n\r\n")?;
+        (&raw[..end], &raw[end + 4..])
+    };
+    let headers = http::parse_headers(&String::from_utf8_lossy(head));
+    let disposition = headers.get("content-disposition")?;
+    let name = parameter(disposition, "name")?;
+    let filename = parameter(disposition, "filename");
+    Some(Part {
+        name,
+        filename,
+        content: content.to_vec(),
+        headers,
+    })
+}
+
+// the value of a `key=value` parameter of a header, unquoted. semicolons inside
+// quotes don't separate parameters, .e.g. `filename="a;b.txt"`.
+pub(crate) fn parameter(header: &str, key: &str) -> Option<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in header.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ';' if !quoted => params.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    params.push(current);
+    params.iter().skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case(key) {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        Some(value.to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_field_and_a_file_are_extracted() {
+        let body = b"preamble\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            holiday\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\
+            \r\n\
+            \xff\xd8\r\n--not-the-boundary\r\n\
+            --xyz--\r\n\
+            epilogue";
+        let parts = parse_multipart(body, "xyz");
+        assert_eq!(parts.len(), 2);
+        assert_eq!((parts[0].name.as_str(), parts[0].filename.as_deref()), ("title", None));
+        assert_eq!(parts[0].content, b"holiday");
+        assert_eq!((parts[1].name.as_str(), parts[1].filename.as_deref()), ("photo", Some("beach.jpg")));
+        assert_eq!(parts[1].headers.get("content-type"), Some("image/jpeg"));
+        assert_eq!(parts[1].content, b"\xff\xd8\r\n--not-the-boundary");
+    }
+
+    #[test]
+    fn the_boundary_comes_from_the_content_type() {
+        assert_eq!(boundary("multipart/form-data; boundary=xyz").as_deref(), Some("xyz"));
+        assert_eq!(boundary("Multipart/Form-Data; boundary=\"a b\"").as_deref(), Some("a b"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("text/plain; boundary=xyz"), None);
+        // cut off before the closing delimiter, the unfinished part is dropped.
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--xyz\r\n\
+            Content-Disposition: form-data; name=\"b\"\r\n\r\n2";
+        let names: Vec<String> = parse_multipart(body, "xyz").into_iter().map(|part| part.name).collect();
+        assert_eq!(names, ["a"]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// how often buckets that filled up again are dropped, so the map doesn't keep every
+// address that ever connected.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many requests a single client may send.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    // tokens added back per second, the sustained request rate.
+    pub rate: f64,
+    // the most tokens a bucket holds, how many requests may come in a burst.
+    pub burst: u32,
+}
+
+/// A token bucket per client address, shared by every worker of the pool.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_cleanup: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    // when *tokens* was last brought up to date.
+    updated: Instant,
+}
+impl Bucket {
+    fn refill(&mut self, now: Instant, limit: &RateLimit) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            state: Arc::new(Mutex::new(State {
+                buckets: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+    /// Take a token from the client's bucket, see *check_at*.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+    /// Take a token from the client's bucket as of *now*.
+    ///
+    /// # Errors
+    ///
+    /// Returns how long until the next token is available if the bucket is empty.
+    pub fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let limit = self.limit;
+        let mut state = self.state.lock().unwrap();
+        if now.saturating_duration_since(state.last_cleanup) >= CLEANUP_INTERVAL {
+            // a full bucket is the same as no bucket at all.
+            state.buckets.retain(|_, bucket| {
+                bucket.refill(now, &limit);
+                bucket.tokens < f64::from(limit.burst)
+            });
+            state.last_cleanup = now;
+        }
+        let bucket = state.buckets.entry(client).or_insert_with(|| Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        bucket.refill(now, &limit);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = if limit.rate > 0.0 { (1.0 - bucket.tokens) / limit.rate } else { f64::MAX };
+        Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+    }
+    /// The number of clients currently tracked.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().buckets.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_client_is_limited_once_its_burst_is_spent() {
+        let limiter = RateLimiter::new(RateLimit { rate: 2.0, burst: 5 });
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(limiter.check_at(client, now), Ok(()));
+        }
+        // two tokens a second, the next one is half a second away.
+        assert_eq!(limiter.check_at(client, now), Err(Duration::from_millis(500)));
+        // another client has its own bucket.
+        assert_eq!(limiter.check_at("192.0.2.2".parse().unwrap(), now), Ok(()));
+        assert_eq!(limiter.check_at(client, now + Duration::from_millis(500)), Ok(()));
+        assert!(limiter.check_at(client, now + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn full_buckets_are_dropped_by_the_cleanup() {
+        let limiter = RateLimiter::new(RateLimit { rate: 1.0, burst: 2 });
+        let now = Instant::now();
+        for i in 0..10u8 {
+            limiter.check_at(IpAddr::from([192, 0, 2, i]), now).unwrap();
+        }
+        assert_eq!(limiter.len(), 10);
+        // the ten buckets filled up again by then, only the client asking is left.
+        let later = now + CLEANUP_INTERVAL;
+        limiter.check_at("198.51.100.1".parse().unwrap(), later).unwrap();
+        assert_eq!(limiter.len(), 1);
+    }
+}
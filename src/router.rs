@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use crate::http::{Request, Response, StatusCode};
+use crate::server::Stream;
+use crate::websocket::UpgradeHandler;
+
+/// A request handler, shared by every worker of the pool.
+pub type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+/// Wraps the handling of every request, calling *next* hands the request on inwards.
+pub type Middleware = dyn Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync;
+
+/// Maps request methods and paths to handlers.
+///
+/// A path segment starting with `:` is a parameter, .e.g. registering `/users/:id`
+/// matches `/users/42` and hands the handler a request whose `params` hold `id = 42`.
+/// When several routes match, static segments win over parameters. A trailing slash
+/// doesn't matter, `/users/42/` is the same as `/users/42`. A path registered for
+/// other methods only is answered with a 405 listing them in `Allow`, and `OPTIONS`
+/// is answered for every registered path.
+pub struct Router {
+    routes: Vec<Route>,
+    // paths taking WebSocket connections, matched the same way as *routes*.
+    websockets: Vec<WebSocketRoute>,
+    // *None* leaves unmatched requests to the server's "404.html", see *set_not_found*.
+    not_found: Option<Box<Handler>>,
+    // in registration order, the first one is the outermost.
+    middlewares: Vec<Box<Middleware>>,
+}
+
+struct Route {
+    method: String,
+    segments: Vec<Segment>,
+    handler: Box<Handler>,
+}
+
+struct WebSocketRoute {
+    segments: Vec<Segment>,
+    handler: Box<UpgradeHandler>,
+}
+
+#[derive(PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    // empty segments come from leading, trailing or doubled slashes.
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn parse_segments(path: &str) -> Vec<Segment> {
+    segments(path)
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => Segment::Param(name.to_string()),
+            None => Segment::Static(segment.to_string()),
+        })
+        .collect()
+}
+
+// the parameters captured from the path, if the pattern matches it.
+fn match_segments(pattern: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut parts = segments(path);
+    for segment in pattern {
+        let part = parts.next()?;
+        match segment {
+            Segment::Static(name) if name == part => {}
+            Segment::Static(_) => return None,
+            Segment::Param(name) => {
+                params.insert(name.clone(), part.to_string());
+            }
+        }
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+// compared position by position, a static segment ranks above a parameter.
+fn specificity(pattern: &[Segment]) -> Vec<bool> {
+    pattern
+        .iter()
+        .map(|segment| matches!(segment, Segment::Static(_)))
+        .collect()
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        match_segments(&self.segments, path)
+    }
+    fn specificity(&self) -> Vec<bool> {
+        specificity(&self.segments)
+    }
+}
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            websockets: Vec::new(),
+            not_found: None,
+            middlewares: Vec::new(),
+        }
+    }
+    /// Register a handler for `GET` requests to the path.
+    pub fn get<F>(&mut self, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            self.add("GET", path, handler)
+        }
+    /// Register a handler for `POST` requests to the path.
+    pub fn post<F>(&mut self, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            self.add("POST", path, handler)
+        }
+    /// Register a handler for `PUT` requests to the path.
+    pub fn put<F>(&mut self, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            self.add("PUT", path, handler)
+        }
+    /// Register a handler for `DELETE` requests to the path.
+    pub fn delete<F>(&mut self, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            self.add("DELETE", path, handler)
+        }
+    /// Register a handler for requests with the given method to the path.
+    pub fn add<F>(&mut self, method: &str, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            let method = method.to_ascii_uppercase();
+            let segments = parse_segments(path);
+            // registering the same method and pattern again replaces the earlier handler.
+            self.routes.retain(|route| route.method != method || route.segments != segments);
+            self.routes.push(Route { method, segments, handler: Box::new(handler) });
+            self
+        }
+    /// Take WebSocket connections on the path, see *websocket::handshake*.
+    ///
+    /// Once the server answered the upgrade with a `101`, the handler gets the request
+    /// (with the `params` of the path) and the raw connection, which is closed when it
+    /// returns. The connection keeps its worker busy all that time. A request to the
+    /// path that doesn't ask for an upgrade gets a `426 Upgrade Required`, and so does
+    /// every request with *server::serve_async*, which can't hand connections over.
+    pub fn websocket<F>(&mut self, path: &str, handler: F) -> &mut Router
+        where F: Fn(&Request, &mut dyn Stream) + Send + Sync + 'static, {
+            let segments = parse_segments(path);
+            self.websockets.retain(|route| route.segments != segments);
+            self.websockets.push(WebSocketRoute { segments, handler: Box::new(handler) });
+            self
+        }
+    /// The handler registered with *websocket* for the path and the parameters captured
+    /// from it, if there's one.
+    pub fn websocket_handler(&self, path: &str) -> Option<(&UpgradeHandler, HashMap<String, String>)> {
+        self.websockets
+            .iter()
+            .filter_map(|route| match_segments(&route.segments, path).map(|params| (route, params)))
+            .max_by_key(|(route, _)| specificity(&route.segments))
+            .map(|(route, params)| (&*route.handler, params))
+    }
+    /// Answer requests nothing else matched with the handler, instead of the
+    /// static `404.html`, .e.g. with JSON for API paths.
+    pub fn set_not_found<F>(&mut self, handler: F) -> &mut Router
+        where F: Fn(&Request) -> Response + Send + Sync + 'static, {
+            self.not_found = Some(Box::new(handler));
+            self
+        }
+    /// The response of the handler set with *set_not_found*, if there's one.
+    pub fn not_found(&self, request: &Request) -> Option<Response> {
+        self.not_found.as_ref().map(|handler| handler(request))
+    }
+    /// Register a middleware, which sees every request before the handler and every
+    /// response after it.
+    ///
+    /// Middlewares run in registration order around the handler, .e.g. one adding a
+    /// header: `router.use_middleware(|request, next| next(request).header("X-Served-By", "me"))`.
+    /// Not calling *next* short-circuits the request with the middleware's own response.
+    pub fn use_middleware<F>(&mut self, middleware: F) -> &mut Router
+        where F: Fn(&Request, &dyn Fn(&Request) -> Response) -> Response + Send + Sync + 'static, {
+            self.middlewares.push(Box::new(middleware));
+            self
+        }
+    /// Run the request through the middlewares, with *handler* innermost.
+    pub fn wrap(&self, request: &Request, handler: &dyn Fn(&Request) -> Response) -> Response {
+        self.wrap_from(0, request, handler)
+    }
+    fn wrap_from(&self, index: usize, request: &Request, handler: &dyn Fn(&Request) -> Response) -> Response {
+        match self.middlewares.get(index) {
+            Some(middleware) => middleware(request, &|request| self.wrap_from(index + 1, request, handler)),
+            None => handler(request),
+        }
+    }
+    /// Run the handler registered for the request, if there's one.
+    ///
+    /// The middlewares are left out, see *dispatch* and *wrap*.
+    ///
+    /// A path that's only registered for other methods gives a `405 Method Not Allowed`,
+    /// or a `204 No Content` for an `OPTIONS` request, both listing them in `Allow`.
+    pub fn route(&self, request: &Request) -> Option<Response> {
+        let matching: Vec<_> = self
+            .routes
+            .iter()
+            .filter_map(|route| route.matches(&request.path).map(|params| (route, params)))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        let best = |method: &str| {
+            matching
+                .iter()
+                .filter(|(route, _)| route.method == method)
+                .max_by_key(|(route, _)| route.specificity())
+        };
+        // a HEAD without a route of its own is answered like the GET, the body is
+        // dropped before it's written.
+        let found = match best(&request.method) {
+            None if request.method == "HEAD" => best("GET"),
+            found => found,
+        };
+        let (route, params) = match found {
+            Some((route, params)) => (route, params.clone()),
+            // answered for the handlers, unless one is registered for OPTIONS itself.
+            None if request.method == "OPTIONS" => {
+                let allowed = self.allowed_methods(&request.path);
+                return Some(Response::new(StatusCode::NO_CONTENT).header("Allow", &allowed.join(", ")));
+            }
+            None => {
+                let allowed = self.allowed_methods(&request.path);
+                return Some(Response::new(StatusCode::METHOD_NOT_ALLOWED).header("Allow", &allowed.join(", ")));
+            }
+        };
+        let mut request = request.clone();
+        request.params = params;
+        Some((route.handler)(&request))
+    }
+    /// The methods with a route matching the path, sorted and without duplicates.
+    pub fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        let mut allowed: Vec<&str> = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(path).is_some())
+            .map(|route| route.method.as_str())
+            .collect();
+        allowed.sort_unstable();
+        allowed.dedup();
+        allowed
+    }
+    /// Run the handler registered for the request through the middlewares, falling
+    /// back to a 404.
+    pub fn dispatch(&self, request: &Request) -> Response {
+        self.wrap(request, &|request| {
+            self.route(request)
+                .or_else(|| self.not_found(request))
+                .unwrap_or_else(|| Response::new(StatusCode::NOT_FOUND))
+        })
+    }
+}
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        Request::parse(format!("{} {} HTTP/1.1\r\n\r\n", method, path).as_bytes()).unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8_lossy(&response.body).into_owned()
+    }
+
+    #[test]
+    fn requests_go_to_the_handler_of_their_path() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::new(StatusCode::OK).body("home"));
+        router.get("/about", |_| Response::new(StatusCode::OK).body("about"));
+        assert_eq!(body(&router.dispatch(&request("GET", "/"))), "home");
+        assert_eq!(body(&router.dispatch(&request("GET", "/about"))), "about");
+        assert_eq!(router.dispatch(&request("GET", "/contact")).status, StatusCode::NOT_FOUND);
+        assert!(router.route(&request("GET", "/contact")).is_none());
+    }
+
+    #[test]
+    fn every_parameter_of_a_route_is_captured() {
+        let mut router = Router::new();
+        router.get("/posts/:post/comments/:comment", |request| {
+            let params = &request.params;
+            Response::new(StatusCode::OK).body(format!("{}/{}", params["post"], params["comment"]))
+        });
+        router.get("/posts/latest/comments/:comment", |_| Response::new(StatusCode::OK).body("latest"));
+        assert_eq!(body(&router.dispatch(&request("GET", "/posts/7/comments/42"))), "7/42");
+        // the same route, with or without the trailing slash.
+        assert_eq!(body(&router.dispatch(&request("GET", "/posts/7/comments/42/"))), "7/42");
+        // a static segment wins over a parameter.
+        assert_eq!(body(&router.dispatch(&request("GET", "/posts/latest/comments/1"))), "latest");
+        assert_eq!(router.dispatch(&request("GET", "/posts/7/comments")).status, StatusCode::NOT_FOUND);
+        assert_eq!(router.dispatch(&request("GET", "/posts/7/comments/42/x")).status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn a_trailing_slash_on_the_route_does_not_matter_either() {
+        let mut router = Router::new();
+        router.get("/users/:id/", |request| Response::new(StatusCode::OK).body(request.params["id"].clone()));
+        assert_eq!(body(&router.dispatch(&request("GET", "/users/42"))), "42");
+        assert_eq!(body(&router.dispatch(&request("GET", "/users/42/"))), "42");
+    }
+
+    #[test]
+    fn middlewares_wrap_every_request_in_registration_order() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::new(StatusCode::OK).body("home"));
+        router.use_middleware(|request, next| next(request).header("X-Request-Id", "abc-123"));
+        router.use_middleware(|request, next| {
+            if request.path == "/blocked" {
+                return Response::new(StatusCode::FORBIDDEN);
+            }
+            next(request).header("X-Inner", "yes")
+        });
+        let response = router.dispatch(&request("GET", "/"));
+        assert_eq!(body(&response), "home");
+        assert_eq!(response.header_value("X-Request-Id"), Some("abc-123"));
+        // the outer one adds its header last.
+        let names: Vec<&str> = response.headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["X-Inner", "X-Request-Id"]);
+        // unrouted and short-circuited requests go through them too.
+        assert_eq!(router.dispatch(&request("GET", "/nowhere")).header_value("X-Request-Id"), Some("abc-123"));
+        let response = router.dispatch(&request("GET", "/blocked"));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.header_value("X-Request-Id"), Some("abc-123"));
+        assert_eq!(response.header_value("X-Inner"), None);
+    }
+
+    #[test]
+    fn each_method_of_a_path_has_its_own_handler() {
+        let mut router = Router::new();
+        router.get("/items", |_| Response::new(StatusCode::OK).body("list"));
+        router.post("/items", |_| Response::new(StatusCode::CREATED).body("created"));
+        let response = router.dispatch(&request("GET", "/items"));
+        assert_eq!((response.status, body(&response)), (StatusCode::OK, "list".to_string()));
+        let response = router.dispatch(&request("POST", "/items"));
+        assert_eq!((response.status, body(&response)), (StatusCode::CREATED, "created".to_string()));
+        let response = router.dispatch(&request("DELETE", "/items"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.header_value("Allow"), Some("GET, POST"));
+        // a HEAD is answered by the GET handler.
+        assert_eq!(router.dispatch(&request("HEAD", "/items")).status, StatusCode::OK);
+    }
+}
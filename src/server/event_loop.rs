@@ -0,0 +1,568 @@
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use crate::cache::FileCache;
+use crate::http::{ChunkedDecoder, ChunkedStatus, FileBody, HeadParser, HeadStatus, Response, StatusCode};
+use crate::listener::{Connection, Listener};
+use crate::log::{log, Level};
+use crate::router::Router;
+use crate::signal;
+use crate::PoolMetrics;
+use super::{
+    add_server_header, bad_request, bind, check_head, continue_line, expects_continue, log_access,
+    log_connection_error, reject_connection, rejected_head, request_line, serve_request, target_too_long, timed_out,
+    is_transient_accept_error, write_response, Context, Framing, ServerConfig, Stream, ACCEPT_ERROR_BACKOFF,
+    ACCEPT_POLL_INTERVAL,
+};
+
+// how much of a file body is read into memory at a time.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serve the static directory on a single thread, see *serve_async*.
+///
+/// # Errors
+///
+/// Returns an error if the CORS config is invalid, the address can't be bound or the
+/// access log can't be opened.
+pub fn run_async(config: ServerConfig) -> Result<(), Box<dyn Error>> {
+    serve_async(config, Router::new())
+}
+
+/// Like *serve*, but every connection is handled on the calling thread by an event
+/// loop over non-blocking sockets instead of on a pool.
+///
+/// An idle keep-alive connection costs a buffer rather than a worker, so many of them
+/// can be held open at once. A slow handler stalls every connection though, and
+/// `pool_size` is ignored. On Ctrl-C (or SIGTERM) idle connections are closed and the
+/// ones in the middle of a request are answered before returning.
+///
+/// # Errors
+///
+/// Returns an error if the CORS config is invalid, a static file can't be preloaded, the
+/// address can't be bound, the access log can't be opened or waiting on the sockets fails.
+pub fn serve_async(config: ServerConfig, router: Router) -> Result<(), Box<dyn Error>> {
+    let cache = FileCache::new();
+    let listener = bind(&config, &cache)?;
+    // there's no pool, so its counters stay at zero.
+    let context = Context::new(config, router, cache, PoolMetrics::default())?;
+    run_loop(&listener, &context, signal::install_shutdown_handler())?;
+    Ok(())
+}
+
+// the event loop of *serve_async*, until *shutdown* is set and the last busy
+// connection is answered.
+fn run_loop(listener: &Listener, context: &Context, shutdown: &AtomicBool) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let mut clients: Vec<Client> = Vec::new();
+    let mut served = 0;
+    // the listener isn't polled until then after a transient error, it would be ready
+    // again right away.
+    let mut accept_paused_until = Instant::now();
+    loop {
+        let stopping = shutdown.load(Ordering::SeqCst);
+        let accepting = !stopping && Instant::now() >= accept_paused_until;
+        if stopping {
+            clients.retain(|client| !client.is_idle());
+            if clients.is_empty() {
+                break;
+            }
+        }
+        // the listener comes first, then every client in order.
+        let mut fds = Vec::with_capacity(clients.len() + 1);
+        fds.push(sys::PollFd::new(listener.as_raw_fd(), if accepting { sys::POLLIN } else { 0 }));
+        fds.extend(clients.iter().map(|client| sys::PollFd::new(client.connection.as_raw_fd(), client.interest())));
+        // woken up in time for the next connection to expire, and to see the shutdown flag.
+        let now = Instant::now();
+        let timeout = clients
+            .iter()
+            .map(|client| client.expires().saturating_duration_since(now))
+            .fold(ACCEPT_POLL_INTERVAL, |timeout, left| timeout.min(left));
+        sys::wait(&mut fds, timeout)?;
+
+        for (client, fd) in clients.iter_mut().zip(&fds[1..]) {
+            if fd.is_ready() {
+                client.on_ready(context);
+            }
+        }
+        let now = Instant::now();
+        for client in &mut clients {
+            if !client.closed && now >= client.expires() {
+                // an idle connection just goes away, like *read_head* lets it.
+                if !client.is_idle() {
+                    log_connection_error(&timed_out());
+                }
+                client.closed = true;
+            }
+        }
+        let before = clients.len();
+        clients.retain(|client| !client.closed);
+        served += before - clients.len();
+        // accepted last, so *fds* still lines up with the clients above.
+        if accepting && fds[0].is_ready() {
+            match accept(listener, &mut clients, context) {
+                Err(ref e) if is_transient_accept_error(e) => {
+                    log(Level::Warn, &format!("failed to accept a connection, backing off: {}", e));
+                    accept_paused_until = Instant::now() + ACCEPT_ERROR_BACKOFF;
+                }
+                accepted => accepted?,
+            }
+        }
+    }
+
+    log(Level::Info, &format!("Served {} connections before exit.", served));
+    Ok(())
+}
+
+// take every connection waiting on the listener.
+fn accept(listener: &Listener, clients: &mut Vec<Client>, context: &Context) -> io::Result<()> {
+    let config = &context.config;
+    loop {
+        let connection = match listener.accept() {
+            Ok(connection) => connection,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if clients.len() >= config.max_connections {
+            // written blocking, its write timeout bounds how long that can stall the loop.
+            connection.set_nonblocking(false)?;
+            if let Err(e) = reject_connection(connection, context) {
+                log_connection_error(&e);
+            }
+            continue;
+        }
+        connection.set_nonblocking(true)?;
+        if let Err(e) = connection.set_nodelay(config.tcp_nodelay) {
+            log(Level::Warn, &format!("failed to set TCP_NODELAY: {}", e));
+        }
+        clients.push(Client::new(connection, config));
+    }
+}
+
+enum State {
+    // waiting for (the rest of) a request.
+    Reading,
+    // *written* bytes of the serialized response are out already, the response itself
+    // is kept for the access log. a file body follows *out*, read into it piece by piece.
+    Writing {
+        out: Vec<u8>,
+        written: usize,
+        file: Option<FileBody>,
+        keep_alive: bool,
+        response: Response,
+        request_line: String,
+    },
+}
+
+// a connection of the event loop, the same requests and responses as *handle_connection*
+// goes through, only never waiting on the socket.
+struct Client {
+    connection: Connection,
+    peer: Option<SocketAddr>,
+    // read but not yet consumed, the start of a pipelined request may follow the current one.
+    buffer: Vec<u8>,
+    // how far the head of the request at the start of *buffer* got.
+    head: HeadParser,
+    // how far its chunked body got, if it has one.
+    chunked: ChunkedDecoder,
+    state: State,
+    served: usize,
+    // *request_timeout* and *idle_timeout* from when the next request was awaited.
+    deadline: Instant,
+    idle_until: Instant,
+    // when the head of the current request was complete, for the duration histogram.
+    started: Option<Instant>,
+    // when the first byte of the current request was read, and when the last bytes were.
+    arrived: Option<Instant>,
+    last_read: Instant,
+    // the part of a "100 Continue" the socket didn't take yet, it goes out ahead of anything else.
+    interim: Vec<u8>,
+    // the current request was told to go ahead already.
+    continued: bool,
+    // the peer shut down its side, what it sent before is still answered.
+    eof: bool,
+    closed: bool,
+}
+impl Client {
+    fn new(connection: Connection, config: &ServerConfig) -> Client {
+        let now = Instant::now();
+        Client {
+            peer: connection.peer_addr(),
+            connection,
+            buffer: Vec::new(),
+            head: HeadParser::new(),
+            chunked: ChunkedDecoder::new(),
+            state: State::Reading,
+            served: 0,
+            deadline: now + config.request_timeout,
+            idle_until: now + config.idle_timeout,
+            started: None,
+            arrived: None,
+            last_read: now,
+            interim: Vec::new(),
+            continued: false,
+            eof: false,
+            closed: false,
+        }
+    }
+    fn interest(&self) -> sys::Events {
+        match self.state {
+            State::Reading if self.interim.is_empty() => sys::POLLIN,
+            State::Reading => sys::POLLIN | sys::POLLOUT,
+            State::Writing { .. } => sys::POLLOUT,
+        }
+    }
+    // nothing of a request arrived yet.
+    fn is_idle(&self) -> bool {
+        matches!(self.state, State::Reading) && self.buffer.is_empty()
+    }
+    fn expires(&self) -> Instant {
+        if self.is_idle() {
+            self.deadline.min(self.idle_until)
+        } else {
+            self.deadline
+        }
+    }
+    fn on_ready(&mut self, context: &Context) {
+        if let Err(e) = self.drive(context) {
+            log_connection_error(&e);
+            self.closed = true;
+        }
+    }
+    fn drive(&mut self, context: &Context) -> io::Result<()> {
+        if let State::Reading = self.state {
+            self.write_interim()?;
+            self.fill(&context.config)?;
+        }
+        loop {
+            if let State::Reading = self.state {
+                match self.take_request(context)? {
+                    Some(mut writing) => {
+                        if let State::Writing { out, .. } = &mut writing {
+                            out.splice(0..0, self.interim.drain(..));
+                        }
+                        self.state = writing;
+                    }
+                    None => {
+                        // the rest of the request is never going to arrive.
+                        self.closed = self.eof;
+                        return Ok(());
+                    }
+                }
+            }
+            if !self.flush()? {
+                return Ok(());
+            }
+            self.finish(context);
+            if self.closed {
+                return Ok(());
+            }
+        }
+    }
+    // read whatever the socket has.
+    fn fill(&mut self, config: &ServerConfig) -> io::Result<()> {
+        // never more than the largest request that could still be served.
+        let limit = config.max_header_size + config.max_body_size;
+        let mut chunk = [0; 4096];
+        while self.buffer.len() <= limit {
+            match self.connection.read(&mut chunk) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(size) => {
+                    self.last_read = Instant::now();
+                    self.arrived.get_or_insert(self.last_read);
+                    self.buffer.extend_from_slice(&chunk[..size]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    // the response to the request at the start of the buffer, once all of it is there.
+    // the head is parsed again every time more of the body arrives, which is cheap next
+    // to reading it.
+    fn take_request(&mut self, context: &Context) -> io::Result<Option<State>> {
+        let config = &context.config;
+        if target_too_long(&self.buffer, config.max_uri_length) {
+            return self.reject(StatusCode::URI_TOO_LONG, config).map(Some);
+        }
+        let head = match self.head.advance(&self.buffer) {
+            HeadStatus::NeedMore if self.buffer.len() > config.max_header_size => None,
+            HeadStatus::NeedMore => return Ok(None),
+            HeadStatus::Complete(_, head_end) if head_end > config.max_header_size => None,
+            HeadStatus::Complete(request, head_end) => Some(Ok((*request, head_end))),
+            HeadStatus::Error(e) => Some(Err(e)),
+        };
+        let head = match head {
+            Some(head) => head,
+            None => return self.reject(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, config).map(Some),
+        };
+        self.started.get_or_insert_with(Instant::now);
+        let request_line = request_line(&self.buffer);
+        let arrived = self.arrived.unwrap_or(self.last_read);
+        let parsed = head.map_err(rejected_head).and_then(|(request, head_end)| {
+            check_head(request, self.peer, config, arrived).map(|parsed| (parsed, head_end))
+        });
+        let ((mut request, framing), head_end) = match parsed {
+            Ok(parsed) => parsed,
+            // the request was rejected before it could be served, don't trust the connection either.
+            Err(response) => {
+                self.served += 1;
+                return writing(response, false, request_line, config).map(Some);
+            }
+        };
+        let body = match framing {
+            Framing::Length(length) if self.buffer.len() >= head_end + length => {
+                Some((self.buffer[head_end..head_end + length].to_vec(), length))
+            }
+            Framing::Length(_) => None,
+            Framing::Chunked => match self.chunked.advance(&self.buffer[head_end..]) {
+                ChunkedStatus::Complete(body, length) if length <= config.max_body_size => Some((body, length)),
+                ChunkedStatus::NeedMore if self.buffer.len() - head_end <= config.max_body_size => None,
+                // counted as sent, see *read_chunked*.
+                status => {
+                    let response = match status {
+                        ChunkedStatus::Invalid => bad_request("malformed chunked body"),
+                        _ => Response::new(StatusCode::PAYLOAD_TOO_LARGE),
+                    };
+                    self.served += 1;
+                    return writing(response, false, request_line, config).map(Some);
+                }
+            },
+        };
+        let (body, length) = match body {
+            Some(body) => body,
+            None => {
+                // the client holds the body back until it's told to go ahead, see *read_request*.
+                if !self.continued && expects_continue(&request) {
+                    self.continued = true;
+                    self.interim = continue_line();
+                    self.write_interim()?;
+                }
+                return Ok(None);
+            }
+        };
+        request.body = body;
+        self.buffer.drain(..head_end + length);
+        // a pipelined request behind this one came in with the last read.
+        self.arrived = if self.buffer.is_empty() { None } else { Some(self.last_read) };
+        self.head.reset();
+        self.chunked.reset();
+        self.served += 1;
+        let (response, keep_alive) = serve_request(request, context, self.served);
+        writing(response, keep_alive, request_line, config).map(Some)
+    }
+    // a head that can't be read at all, answered without a request line to log.
+    fn reject(&mut self, status: StatusCode, config: &ServerConfig) -> io::Result<State> {
+        self.started = None;
+        writing(Response::new(status), false, "-".to_string(), config)
+    }
+    // write as much of the interim response as the socket takes.
+    fn write_interim(&mut self) -> io::Result<()> {
+        while !self.interim.is_empty() {
+            match self.connection.write(&self.interim) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => {
+                    self.interim.drain(..size);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    // write as much of the response as the socket takes, true once all of it is out.
+    fn flush(&mut self) -> io::Result<bool> {
+        if let State::Writing { out, written, file, .. } = &mut self.state {
+            loop {
+                while *written < out.len() {
+                    match self.connection.write(&out[*written..]) {
+                        Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                        Ok(size) => *written += size,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                let body = match file {
+                    Some(body) => body,
+                    None => break,
+                };
+                // the next piece of the file replaces what went out.
+                out.resize(FILE_CHUNK_SIZE, 0);
+                let size = body.read(out)?;
+                out.truncate(size);
+                *written = 0;
+                if size == 0 {
+                    *file = None;
+                }
+            }
+        }
+        Ok(true)
+    }
+    // the response went out, await the next request or close the connection.
+    fn finish(&mut self, context: &Context) {
+        let config = &context.config;
+        if let State::Writing { keep_alive, response, request_line, .. } = mem::replace(&mut self.state, State::Reading) {
+            if let Some(started) = self.started.take() {
+                context.response_sizes.observe(response.body_length());
+                context.request_durations.observe(started.elapsed().as_millis() as u64);
+            }
+            log_access(context, self.peer, &request_line, &response);
+            if !keep_alive || (self.eof && self.buffer.is_empty()) {
+                self.closed = true;
+                return;
+            }
+            self.continued = false;
+            let now = Instant::now();
+            self.deadline = now + config.request_timeout;
+            self.idle_until = now + config.idle_timeout;
+        }
+    }
+}
+
+// serialize the response up front, it's written out over as many wakeups as it takes.
+// only the head of a response with a file body is, the file is read as it goes out.
+fn writing(response: Response, keep_alive: bool, request_line: String, config: &ServerConfig) -> io::Result<State> {
+    let mut response = response.header("Connection", if keep_alive { "keep-alive" } else { "close" });
+    let file = response.file.clone();
+    let out = if file.is_some() {
+        add_server_header(&mut response, config);
+        response.head().into_bytes()
+    } else {
+        let mut out = io::Cursor::new(Vec::new());
+        write_response(&mut out, &mut response, config)?;
+        out.into_inner()
+    };
+    Ok(State::Writing {
+        out,
+        written: 0,
+        file,
+        keep_alive,
+        response,
+        request_line,
+    })
+}
+
+// waiting on many sockets at once with "poll", which every Unix has.
+mod sys {
+    use std::io;
+    use std::os::raw::{c_int, c_short};
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    pub type Events = c_short;
+
+    pub const POLLIN: Events = 0x1;
+    pub const POLLOUT: Events = 0x4;
+
+    #[cfg(target_os = "linux")]
+    type Nfds = std::os::raw::c_ulong;
+    #[cfg(not(target_os = "linux"))]
+    type Nfds = std::os::raw::c_uint;
+
+    #[repr(C)]
+    pub struct PollFd {
+        fd: c_int,
+        events: Events,
+        revents: Events,
+    }
+    impl PollFd {
+        pub fn new(fd: RawFd, events: Events) -> PollFd {
+            PollFd { fd, events, revents: 0 }
+        }
+        // readable, writable, or hung up or failed, which the next read or write reports.
+        pub fn is_ready(&self) -> bool {
+            self.revents != 0
+        }
+    }
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+    }
+
+    // wait until one of the descriptors is ready or the timeout passed. a signal
+    // interrupting the wait isn't an error, the caller checks the shutdown flag next.
+    pub fn wait(fds: &mut [PollFd], timeout: Duration) -> io::Result<()> {
+        // rounded up, so a deadline less than a millisecond away isn't spun on.
+        let millis = timeout.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int;
+        // *fds* is valid for its whole length, and "poll" only writes the "revents" of it.
+        let result = unsafe { poll(fds.as_mut_ptr(), fds.len() as Nfds, millis) };
+        if result == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // one response off a connection that stays open, framed by its "Content-Length".
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        let length = head
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        head + &String::from_utf8_lossy(&body)
+    }
+
+    #[test]
+    fn one_thread_serves_many_connections_at_once() {
+        let mut router = Router::new();
+        router.get("/:n", |request| Response::new(StatusCode::OK).body(request.params["n"].clone()));
+        let config = ServerConfig { access_log: None, ..ServerConfig::default() };
+        let context = Context::new(config, router, FileCache::new(), PoolMetrics::default()).unwrap();
+        let listener = Listener::bind("127.0.0.1:0", true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let server = thread::spawn(move || run_loop(&listener, &context, &stopped));
+
+        // all of them open before any is answered, a blocking server would serve them one by one.
+        let mut clients: Vec<TcpStream> = (0..20).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        for (n, client) in clients.iter_mut().enumerate() {
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(format!("GET /{} HTTP/1.1\r\n\r\n", n).as_bytes()).unwrap();
+        }
+        for (n, client) in clients.iter_mut().enumerate().rev() {
+            let response = read_response(client);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with(&format!("\r\n\r\n{}", n)), "{}", response);
+        }
+        // kept alive, a second request on the first connection.
+        clients[0].write_all(b"GET /again HTTP/1.1\r\n\r\n").unwrap();
+        assert!(read_response(&mut clients[0]).ends_with("\r\n\r\nagain"));
+
+        // the idle connections don't hold up the shutdown.
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        let mut rest = Vec::new();
+        assert_eq!(clients[1].read_to_end(&mut rest).unwrap(), 0);
+    }
+}
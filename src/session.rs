@@ -0,0 +1,137 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use crate::http::Request;
+
+/// The cookie carrying the id of a session.
+pub const COOKIE_NAME: &str = "session_id";
+// how often expired sessions are dropped, so the map doesn't keep every client that
+// ever came by.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The data a client's requests share, kept on the server between them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Session {
+    values: HashMap<String, String>,
+}
+impl Session {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.values.remove(key)
+    }
+    /// Forget everything, .e.g. when the user logs out.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+/// The sessions of every client, shared by every worker of the pool.
+///
+/// Set as *ServerConfig::sessions*, the server gives every request a session: the one
+/// named by its `session_id` cookie, or a new one sent along with a `Set-Cookie` for
+/// the response. A handler holding a clone of the store gets at it with *with*.
+/// Sessions nobody used for the TTL are dropped.
+#[derive(Clone)]
+pub struct SessionStore {
+    ttl: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    sessions: HashMap<String, Entry>,
+    last_cleanup: Instant,
+}
+
+struct Entry {
+    session: Session,
+    // refreshed by every request using the session.
+    expires: Instant,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration) -> SessionStore {
+        SessionStore {
+            ttl,
+            state: Arc::new(Mutex::new(State {
+                sessions: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+    /// Run *f* on the session of the request, `None` if the request has none (it didn't
+    /// come through a server keeping sessions in this store, or the session expired
+    /// since).
+    pub fn with<F, T>(&self, request: &Request, f: F) -> Option<T>
+        where F: FnOnce(&mut Session) -> T, {
+            let id = request.session_id.as_ref()?;
+            let mut state = self.state();
+            let entry = state.sessions.get_mut(id).filter(|entry| entry.expires > Instant::now())?;
+            Some(f(&mut entry.session))
+        }
+    /// Drop the session of the request, the client gets a new one with its next request.
+    pub fn destroy(&self, request: &Request) {
+        if let Some(id) = &request.session_id {
+            self.state().sessions.remove(id);
+        }
+    }
+    /// The number of sessions currently stored, expired ones included until the next cleanup.
+    pub fn len(&self) -> usize {
+        self.state().sessions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // a handler panicking in *with* poisons the lock, the sessions are no worse for it
+    // than what the handler left behind, so the other requests go on using them.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    // the id of the session named by the request's cookie, extending its life, or of a
+    // new one along with the "Set-Cookie" value handing it to the client.
+    pub(crate) fn resume(&self, request: &Request) -> (String, Option<String>) {
+        let now = Instant::now();
+        let mut state = self.state();
+        if now.saturating_duration_since(state.last_cleanup) >= CLEANUP_INTERVAL {
+            state.sessions.retain(|_, entry| entry.expires > now);
+            state.last_cleanup = now;
+        }
+        if let Some(id) = request.cookie(COOKIE_NAME) {
+            if let Some(entry) = state.sessions.get_mut(id).filter(|entry| entry.expires > now) {
+                entry.expires = now + self.ttl;
+                return (id.to_string(), None);
+            }
+        }
+        let id = new_id();
+        let entry = Entry { session: Session::default(), expires: now + self.ttl };
+        state.sessions.insert(id.clone(), entry);
+        // without "Max-Age" the browser keeps it until it's closed, the store expires
+        // the session on its own. scripts can't read it.
+        let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", COOKIE_NAME, id);
+        (id, Some(cookie))
+    }
+}
+
+// 128 random bits in hex, from the OS where it has "/dev/urandom". elsewhere from
+// the randomly keyed hasher std uses for *HashMap*, which is harder to guess than a
+// counter but no substitute for a real source of randomness.
+fn new_id() -> String {
+    let mut bytes = [0u8; 16];
+    if File::open("/dev/urandom").and_then(|mut random| random.read_exact(&mut bytes)).is_err() {
+        for (i, half) in bytes.chunks_mut(8).enumerate() {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_usize(i);
+            hasher.write_u128(UNIX_EPOCH.elapsed().unwrap_or_default().as_nanos());
+            half.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,40 @@
+use std::sync::atomic::AtomicBool;
+
+// flipped from the signal handler, which can't capture any state so it has to be a static.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Install handlers for SIGINT (Ctrl-C) and SIGTERM that request a graceful shutdown.
+///
+/// Returns the flag the accept loop should check, it turns `true` once a signal arrived.
+pub fn install_shutdown_handler() -> &'static AtomicBool {
+    #[cfg(unix)]
+    sys::install();
+    &SHUTDOWN
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn on_signal(_: c_int) {
+        // only async-signal-safe work is allowed in here, an atomic store is.
+        super::SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        // the handler only touches an atomic, so it is sound to run at any point.
+        unsafe {
+            signal(SIGINT, on_signal);
+            signal(SIGTERM, on_signal);
+        }
+    }
+}
+
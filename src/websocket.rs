@@ -0,0 +1,213 @@
+/*
+ * The opening handshake of the WebSocket protocol (RFC 6455), after which the
+ * connection is handed to a route's handler as it is. Framing is left to the handler.
+ *
+ * GET /chat HTTP/1.1
+ * Upgrade: websocket
+ * Connection: Upgrade
+ * Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==
+ * Sec-WebSocket-Version: 13
+ *
+ * is answered with
+ *
+ * HTTP/1.1 101 Switching Protocols
+ * Upgrade: websocket
+ * Connection: Upgrade
+ * Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=
+ */
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use crate::http::{Request, Response, StatusCode};
+use crate::server::Stream;
+
+// appended to the client's key before hashing, fixed by the RFC.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The only version of the protocol there is.
+pub const VERSION: &str = "13";
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Takes over a connection once the handshake is done, see *Router::websocket*.
+pub type UpgradeHandler = dyn Fn(&Request, &mut dyn Stream) + Send + Sync;
+
+/// Whether the request asks to switch the connection over to WebSocket, with
+/// `Upgrade: websocket` and `Connection: Upgrade`. Whether it does so correctly is up
+/// to *handshake*.
+pub fn is_upgrade(request: &Request) -> bool {
+    let has_token = |name: &str, token: &str| {
+        request
+            .headers
+            .get_joined(name)
+            .is_some_and(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)))
+    };
+    has_token("upgrade", "websocket") && has_token("connection", "upgrade")
+}
+
+/// The `101 Switching Protocols` accepting an upgrade request.
+///
+/// # Errors
+///
+/// Returns the response turning the request down: a 400 if it isn't a `GET` over
+/// HTTP/1.1 or has no usable `Sec-WebSocket-Key`, a 426 naming the version the server
+/// speaks if it asks for another one.
+pub fn handshake(request: &Request) -> Result<Response, Response> {
+    if request.method != "GET" || request.version != "HTTP/1.1" {
+        return Err(Response::new(StatusCode::BAD_REQUEST));
+    }
+    if request.headers.get("sec-websocket-version").map(|version| version.trim()) != Some(VERSION) {
+        return Err(Response::new(StatusCode::UPGRADE_REQUIRED).header("Sec-WebSocket-Version", VERSION));
+    }
+    // 16 random bytes in base64, nothing else is a valid key.
+    let key = match request.headers.get("sec-websocket-key").map(|key| key.trim()) {
+        Some(key) if key.len() == 24 && key.ends_with("==") => key,
+        _ => return Err(Response::new(StatusCode::BAD_REQUEST)),
+    };
+    Ok(Response::new(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept_key(key)))
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's key: the SHA-1 of the key and the
+/// protocol's GUID, base64-encoded. .e.g. `s3pPLMBiTxaQ9kYGzzhZRbK+xOo=` for the key
+/// `dGhlIHNhbXBsZSBub25jZQ==` of the RFC's example.
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+// FIPS 180-4. broken for signatures, but the handshake only uses it to prove the server
+// understood the request.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    // padded with a 1 bit, zeros and the length in bits to a multiple of 64 bytes.
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (out, state) in digest.chunks_mut(4).zip(h.iter()) {
+        out.copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+// the standard alphabet, padded with "=".
+pub(crate) fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [group[0], *group.get(1).unwrap_or(&0), *group.get(2).unwrap_or(&0)];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The connection a handler takes over, starting with whatever the client sent right
+/// after its handshake and the server had already read.
+pub(crate) struct Upgraded<'a> {
+    buffered: io::Cursor<Vec<u8>>,
+    stream: &'a mut dyn Stream,
+}
+impl<'a> Upgraded<'a> {
+    pub(crate) fn new(buffered: Vec<u8>, stream: &'a mut dyn Stream) -> Upgraded<'a> {
+        Upgraded { buffered: io::Cursor::new(buffered), stream }
+    }
+}
+impl Read for Upgraded<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.buffered.read(buf)? {
+            0 => self.stream.read(buf),
+            size => Ok(size),
+        }
+    }
+}
+impl Write for Upgraded<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+impl Stream for Upgraded<'_> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade(headers: &str) -> Request {
+        let head = "GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n";
+        Request::parse(format!("{}{}\r\n", head, headers).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn the_accept_key_matches_the_rfc_example() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let digest: String = sha1(b"abc").iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(digest, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!([base64(b"f"), base64(b"fo"), base64(b"foo")], ["Zg==", "Zm8=", "Zm9v"]);
+    }
+
+    #[test]
+    fn only_a_valid_upgrade_is_switched() {
+        let request = upgrade("Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n");
+        assert!(is_upgrade(&request));
+        let response = handshake(&request).unwrap();
+        assert_eq!(response.status, StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.header_value("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let request = upgrade("Sec-WebSocket-Version: 8\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n");
+        let other_version = handshake(&request).unwrap_err();
+        assert_eq!(other_version.status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(other_version.header_value("Sec-WebSocket-Version"), Some("13"));
+        let no_key = handshake(&upgrade("Sec-WebSocket-Version: 13\r\n")).unwrap_err();
+        assert_eq!(no_key.status, StatusCode::BAD_REQUEST);
+        assert!(!is_upgrade(&Request::parse(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\n\r\n").unwrap()));
+    }
+}